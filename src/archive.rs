@@ -0,0 +1,186 @@
+use chrono::{DateTime, Utc};
+use csv::{Reader, Writer, WriterBuilder};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use reqwest::header::{HeaderMap, CONTENT_TYPE, ETAG, LAST_MODIFIED};
+use reqwest::{StatusCode, Url};
+use sha2::{Digest, Sha256};
+
+use std::fs::{create_dir_all, rename, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use crate::error::Error;
+use crate::io::{
+  data_path, timestamp_deserializer, timestamp_serializer, url_deserializer, url_serializer,
+};
+
+/// Tells apart the temporary files of bodies being stored at the same time
+static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// The role a fetched page plays in a crawl
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Page {
+  Results,
+  Listing,
+}
+
+/// Index record of a single fetch
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Entry {
+  /// URL
+  #[serde(
+    serialize_with = "url_serializer",
+    deserialize_with = "url_deserializer"
+  )]
+  pub url: Url,
+  /// Time of the fetch
+  #[serde(
+    serialize_with = "timestamp_serializer",
+    deserialize_with = "timestamp_deserializer"
+  )]
+  pub timestamp: DateTime<Utc>,
+  /// Role of the page
+  pub page: Page,
+  /// HTTP status code
+  pub status: u16,
+  /// `Content-Type` header
+  pub content_type: Option<String>,
+  /// `ETag` header
+  pub etag: Option<String>,
+  /// `Last-Modified` header
+  pub last_modified: Option<String>,
+  /// SHA-256 of the body, which is also the key of the stored object
+  pub hash: String,
+  /// Size of the uncompressed body in bytes
+  pub size: usize,
+}
+
+/// A content-addressed store of every fetched page under `~/.truffles/archive`
+///
+/// Bodies are gzipped into `objects/<xx>/<sha256>.html.gz`, so identical pages are only stored
+/// once, while `index.csv` keeps one record per fetch.
+pub struct Archive {
+  dir: PathBuf,
+  index: Mutex<Writer<File>>,
+}
+
+impl Archive {
+  pub fn open() -> Result<Self, Error> {
+    let dir = data_path("archive");
+    create_dir_all(dir.join("objects")).map_err(Error::from)?;
+
+    let index_path = dir.join("index.csv");
+    let index = WriterBuilder::new()
+      .has_headers(
+        index_path
+          .metadata()
+          .map(|meta| meta.len() == 0)
+          .unwrap_or(true),
+      )
+      .from_writer(
+        OpenOptions::new()
+          .create(true)
+          .append(true)
+          .open(index_path)
+          .map_err(Error::from)?,
+      );
+
+    Ok(Self {
+      dir,
+      index: Mutex::new(index),
+    })
+  }
+
+  /// Stores a fetched page and records it in the index
+  pub fn store(
+    &self,
+    url: &Url,
+    page: Page,
+    status: StatusCode,
+    headers: &HeaderMap,
+    body: &str,
+  ) -> Result<Entry, Error> {
+    let hash = hex::encode(Sha256::digest(body.as_bytes()));
+
+    let path = self.object_path(&hash);
+    if !path.exists() {
+      create_dir_all(
+        path
+          .parent()
+          .expect("INTERNAL ERROR: Can't get path's parent"),
+      )
+      .map_err(Error::from)?;
+      // Identical bodies may be fetched at once, so each write goes through its own file
+      let tmp_path = path.with_extension(format!(
+        "{}.{}.tmp",
+        process::id(),
+        TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+      ));
+      let mut encoder = GzEncoder::new(
+        File::create(&tmp_path).map_err(Error::from)?,
+        Compression::default(),
+      );
+      encoder.write_all(body.as_bytes()).map_err(Error::from)?;
+      encoder.finish().map_err(Error::from)?;
+      rename(&tmp_path, &path).map_err(Error::from)?;
+    }
+
+    let header = |name| {
+      headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(String::from)
+    };
+    let entry = Entry {
+      url: url.clone(),
+      timestamp: Utc::now(),
+      page,
+      status: status.as_u16(),
+      content_type: header(CONTENT_TYPE),
+      etag: header(ETAG),
+      last_modified: header(LAST_MODIFIED),
+      hash,
+      size: body.len(),
+    };
+
+    let mut index = self
+      .index
+      .lock()
+      .expect("INTERNAL ERROR: Archive index lock poisoned");
+    index.serialize(&entry).map_err(Error::from)?;
+    index.flush().map_err(Error::from)?;
+
+    Ok(entry)
+  }
+
+  /// Reads back every record in the index, oldest first
+  pub fn entries(&self) -> Result<Vec<Entry>, Error> {
+    Reader::from_path(self.dir.join("index.csv"))
+      .map_err(Error::from)?
+      .into_deserialize()
+      .map(|entry| entry.map_err(Error::from))
+      .collect()
+  }
+
+  /// Reads back the body of an archived page
+  pub fn read(&self, entry: &Entry) -> Result<String, Error> {
+    let mut body = String::new();
+    GzDecoder::new(File::open(self.object_path(&entry.hash)).map_err(Error::from)?)
+      .read_to_string(&mut body)
+      .map_err(Error::from)?;
+    Ok(body)
+  }
+
+  fn object_path(&self, hash: &str) -> PathBuf {
+    self
+      .dir
+      .join("objects")
+      .join(&hash[..2])
+      .join(format!("{}.html.gz", hash))
+  }
+}
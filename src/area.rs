@@ -11,6 +11,12 @@ pub enum Area {
   Lefkosia,
   Limassol,
   Paphos,
+  Attica,
+  Crete,
+  Thessaloniki,
+  Berlin,
+  Hamburg,
+  Munich,
 }
 
 impl FromStr for Area {
@@ -22,6 +28,12 @@ impl FromStr for Area {
       "lefkosia" | "nicosia" => Ok(Self::Lefkosia),
       "limassol" | "lemesos" => Ok(Self::Limassol),
       "paphos" | "pafos" => Ok(Self::Paphos),
+      "attica" | "attiki" | "athens" => Ok(Self::Attica),
+      "crete" | "kriti" => Ok(Self::Crete),
+      "thessaloniki" | "salonica" => Ok(Self::Thessaloniki),
+      "berlin" => Ok(Self::Berlin),
+      "hamburg" => Ok(Self::Hamburg),
+      "munich" | "münchen" | "muenchen" => Ok(Self::Munich),
       _ => Err(format!("Couldn't parse '{}' as an area", s)),
     }
   }
@@ -51,6 +63,34 @@ impl Lookup for Area {
       .is_some()
     {
       Some(Area::Paphos)
+    } else if Regex::new(r"([Aa]tti((ca)|(ki)))|([Aa]thens?)|(Αττική)|(Αθήνα)")
+      .unwrap()
+      .find(from)
+      .is_some()
+    {
+      Some(Area::Attica)
+    } else if Regex::new(r"([Cc]rete)|([Kk]riti)|([Hh]era[kc]li?on)|([Cc]hania)|(Κρήτη)")
+      .unwrap()
+      .find(from)
+      .is_some()
+    {
+      Some(Area::Crete)
+    } else if Regex::new(r"([Tt]hessaloni[ck]i)|(Θεσσαλονίκη)")
+      .unwrap()
+      .find(from)
+      .is_some()
+    {
+      Some(Area::Thessaloniki)
+    } else if Regex::new(r"[Bb]erlin").unwrap().find(from).is_some() {
+      Some(Area::Berlin)
+    } else if Regex::new(r"[Hh]amburg").unwrap().find(from).is_some() {
+      Some(Area::Hamburg)
+    } else if Regex::new(r"([Mm]unich)|([Mm](ü|ue)nchen)")
+      .unwrap()
+      .find(from)
+      .is_some()
+    {
+      Some(Area::Munich)
     } else {
       None
     }
@@ -1,19 +1,35 @@
 use chrono::{DateTime, Utc};
-use csv::{Reader, Writer, WriterBuilder};
+use csv::{Reader, StringRecord, Writer, WriterBuilder};
 use reqwest::Url;
 
 use std::collections::HashMap;
-use std::fs::{create_dir_all, File, OpenOptions};
-use std::path::PathBuf;
+use std::fs::{create_dir_all, rename, File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
+use crate::description::Description;
+use crate::error::Error;
+use crate::images::ListingImage;
+use crate::io::data_path;
 use crate::listing::{Kind, Listing};
-use crate::plot::Plot;
-use crate::property::Property;
+use crate::seller::Seller;
+use crate::validators::Validators;
 
 pub struct Cache {
   listings: Vec<Listing>,
   urls: HashMap<Url, Vec<usize>>,
   writers: HashMap<Kind, Writer<File>>,
+  validators: HashMap<Url, Validators>,
+  validators_writer: Writer<File>,
+  /// Latest description text of every listing
+  descriptions: HashMap<Url, String>,
+  descriptions_writer: Writer<File>,
+  /// Latest image URLs of every listing
+  images: HashMap<Url, Vec<Url>>,
+  images_writer: Writer<File>,
+  /// Latest record of every seller by UID
+  sellers: HashMap<String, Seller>,
+  sellers_writer: Writer<File>,
 }
 
 impl Cache {
@@ -32,6 +48,28 @@ impl Cache {
     })
   }
 
+  /// Returns the most recent listing cached for `url`
+  pub fn get_latest(&self, url: &Url) -> Option<&Listing> {
+    self.urls.get(url).and_then(|indices| {
+      indices
+        .iter()
+        .map(|&index| &self.listings[index])
+        .max_by_key(|listing| *listing.timestamp())
+    })
+  }
+
+  /// Returns the record parsed off the page of `url` that was fetched at `fetched`: the first
+  /// record of the listing being live since then, rather than a later one telling that it ended
+  pub fn get_parsed(&self, url: &Url, fetched: &DateTime<Utc>) -> Option<&Listing> {
+    self.urls.get(url).and_then(|indices| {
+      indices
+        .iter()
+        .map(|&index| &self.listings[index])
+        .filter(|listing| listing.status().is_live() && listing.timestamp() >= fetched)
+        .min_by_key(|listing| *listing.timestamp())
+    })
+  }
+
   pub fn add(&mut self, listing: Listing) {
     let index = self.listings.len();
 
@@ -52,89 +90,168 @@ impl Cache {
       None => panic!("No writer found for listing kind '{:?}'", listing.kind()),
     }
 
+    if let Some(text) = listing.description() {
+      self.set_description(&listing, text);
+    }
+    if !listing.images().is_empty() {
+      self.set_images(&listing);
+    }
+    if let Some(seller) = listing.seller() {
+      self.set_seller(seller);
+    }
+
     self.listings.push(listing);
   }
 
+  /// Returns the latest record of every seller
+  pub fn sellers(&self) -> impl Iterator<Item = &Seller> {
+    self.sellers.values()
+  }
+
+  /// Records `seller`, unless it's the same as the record last kept of it
+  fn set_seller(&mut self, seller: &Seller) {
+    if self.sellers.get(&seller.id) == Some(seller) {
+      return;
+    }
+
+    self
+      .sellers_writer
+      .serialize(seller)
+      .expect("Couldn't serialize seller");
+    self.sellers_writer.flush().expect("Couldn't flush writer");
+    self.sellers.insert(seller.id.clone(), seller.clone());
+  }
+
+  /// Records the description of `listing`, unless it's the same as the one last recorded
+  fn set_description(&mut self, listing: &Listing, text: &str) {
+    if self.descriptions.get(listing.url()).map(String::as_str) == Some(text) {
+      return;
+    }
+
+    let description = Description {
+      id: String::from(listing.id()),
+      url: listing.url().clone(),
+      timestamp: *listing.timestamp(),
+      text: String::from(text),
+    };
+    self
+      .descriptions_writer
+      .serialize(&description)
+      .expect("Couldn't serialize description");
+    self
+      .descriptions_writer
+      .flush()
+      .expect("Couldn't flush writer");
+    self.descriptions.insert(description.url, description.text);
+  }
+
+  /// Records the image URLs of `listing`, unless they're the same as the ones last recorded
+  fn set_images(&mut self, listing: &Listing) {
+    if self.images.get(listing.url()).map(Vec::as_slice) == Some(listing.images()) {
+      return;
+    }
+
+    for (position, image) in listing.images().iter().enumerate() {
+      let record = ListingImage {
+        id: String::from(listing.id()),
+        url: listing.url().clone(),
+        timestamp: *listing.timestamp(),
+        position,
+        image: image.clone(),
+      };
+      self
+        .images_writer
+        .serialize(&record)
+        .expect("Couldn't serialize image");
+    }
+    self.images_writer.flush().expect("Couldn't flush writer");
+    self
+      .images
+      .insert(listing.url().clone(), listing.images().to_vec());
+  }
+
+  /// Returns the most recent record of every cached listing
+  pub fn latest_listings(&self) -> impl Iterator<Item = &Listing> {
+    self.urls.keys().filter_map(move |url| self.get_latest(url))
+  }
+
+  /// Returns the validators of the latest fetch of `url`, if it was ever parsed
+  pub fn get_validators(&self, url: &Url) -> Option<&Validators> {
+    self.validators.get(url)
+  }
+
+  /// Returns the image URLs of the latest gallery of `url`, in order
+  pub fn get_images(&self, url: &Url) -> Option<&Vec<Url>> {
+    self.images.get(url)
+  }
+
+  pub fn set_validators(&mut self, validators: Validators) {
+    if self.validators.get(&validators.url) == Some(&validators) {
+      return;
+    }
+
+    self
+      .validators_writer
+      .serialize(&validators)
+      .expect("Couldn't serialize validators");
+    self
+      .validators_writer
+      .flush()
+      .expect("Couldn't flush writer");
+    self.validators.insert(validators.url.clone(), validators);
+  }
+
+  /// Loads the current generation of the cache from `~/.truffles`
   pub fn load() -> Self {
+    Self::load_from(data_path(""))
+  }
+
+  /// Loads the cache kept in `dir`, creating the directory if it doesn't exist
+  pub fn load_from(dir: PathBuf) -> Self {
     let mut urls: HashMap<Url, Vec<usize>> = HashMap::new();
     let mut listings: Vec<Listing> = vec![];
 
-    // Load up properties if any can be found
-    let props_path = dirs::home_dir()
-      .expect("Couldn't get home directory")
-      .join(PathBuf::from(".truffles/properties.csv"));
-    if props_path.exists() {
-      let reader =
-        Reader::from_path(props_path.clone()).expect("Couldn't open cached property listings");
-      for prop in reader.into_deserialize() {
-        let property: Property = match prop {
-          Ok(property) => property,
-          Err(e) => panic!("Couldn't deserialize property record:{}", e),
-        };
-
-        let index = listings.len();
-
-        match urls.get_mut(&property.url) {
-          Some(ref mut vec) => {
-            vec.push(index);
-          }
-          None => {
-            urls.insert(property.url.clone(), vec![index]);
-          }
-        }
+    create_dir_all(&dir).expect("Can't create cache directory");
 
-        listings.push(Listing::Property(property));
+    // Load up cached listings of every kind
+    for kind in Kind::all().into_iter() {
+      let path = dir.join(format!("{:?}.csv", kind));
+      if !path.exists() {
+        continue;
       }
-    } else {
-      create_dir_all(
-        props_path
-          .parent()
-          .expect("INTERNAL ERROR: Can't get path's parent"),
-      )
-      .expect("Can't create .truffles directory");
-    }
-
-    // Load up plots if any can be found
-    let plots_path = dirs::home_dir()
-      .expect("Couldn't get home directory")
-      .join(PathBuf::from(".truffles/plots.csv"));
-    if plots_path.exists() {
-      let reader =
-        Reader::from_path(plots_path.clone()).expect("Couldn't open cached plot listings");
-      for plot in reader.into_deserialize() {
-        let plot: Plot = match plot {
-          Ok(plot) => plot,
-          Err(e) => panic!("Couldn't deserialize plot record:{}", e),
-        };
+      migrate(&path).unwrap_or_else(|e| panic!("Couldn't migrate {}:{}", path.display(), e));
+
+      let mut reader = Reader::from_path(path).expect("Couldn't open cached listings");
+      let records: Result<Vec<Listing>, _> = match kind {
+        Kind::Plot => reader.deserialize().map(|r| r.map(Listing::Plot)).collect(),
+        Kind::Property => reader
+          .deserialize()
+          .map(|r| r.map(Listing::Property))
+          .collect(),
+      };
+      let records =
+        records.unwrap_or_else(|e| panic!("Couldn't deserialize {:?} record:{}", kind, e));
 
+      for listing in records.into_iter() {
         let index = listings.len();
 
-        match urls.get_mut(&plot.url) {
+        match urls.get_mut(listing.url()) {
           Some(ref mut vec) => {
             vec.push(index);
           }
           None => {
-            urls.insert(plot.url.clone(), vec![index]);
+            urls.insert(listing.url().clone(), vec![index]);
           }
         }
 
-        listings.push(Listing::Plot(plot));
+        listings.push(listing);
       }
-    } else {
-      create_dir_all(
-        plots_path
-          .parent()
-          .expect("INTERNAL ERROR: Can't get path's parent"),
-      )
-      .expect("Can't create .truffles directory");
     }
 
     // Initialize writers
     let mut writers = HashMap::new();
     for kind in Kind::all().into_iter() {
-      let path = dirs::home_dir()
-        .expect("Couldn't get home directory")
-        .join(PathBuf::from(format!(".truffles/{:?}.csv", kind)));
+      let path = dir.join(format!("{:?}.csv", kind));
 
       let writer = WriterBuilder::new()
         .has_headers(!path.exists() || !listings.iter().any(|l| l.kind() == kind))
@@ -148,10 +265,227 @@ impl Cache {
       assert!(writers.insert(kind, writer).is_none());
     }
 
+    // Load up validators, later ones overriding earlier ones
+    let path = dir.join("validators.csv");
+    let mut validators = HashMap::new();
+    if path.exists() {
+      for record in Reader::from_path(&path)
+        .expect("Couldn't open cached validators")
+        .deserialize::<Validators>()
+      {
+        let record = record.unwrap_or_else(|e| panic!("Couldn't deserialize validators:{}", e));
+        validators.insert(record.url.clone(), record);
+      }
+    }
+    let validators_writer = WriterBuilder::new()
+      .has_headers(validators.is_empty())
+      .from_writer(
+        OpenOptions::new()
+          .create(true)
+          .append(true)
+          .open(path)
+          .expect("Couldn't open validators file"),
+      );
+
+    // Load up descriptions, later ones overriding earlier ones
+    let path = dir.join("descriptions.csv");
+    let mut descriptions = HashMap::new();
+    if path.exists() {
+      for record in Reader::from_path(&path)
+        .expect("Couldn't open cached descriptions")
+        .deserialize::<Description>()
+      {
+        let record = record.unwrap_or_else(|e| panic!("Couldn't deserialize description:{}", e));
+        descriptions.insert(record.url, record.text);
+      }
+    }
+    let descriptions_writer = WriterBuilder::new()
+      .has_headers(descriptions.is_empty())
+      .from_writer(
+        OpenOptions::new()
+          .create(true)
+          .append(true)
+          .open(path)
+          .expect("Couldn't open descriptions file"),
+      );
+
+    // Load up image URLs, every listing's gallery starting over at position 0
+    let path = dir.join("images.csv");
+    let mut images: HashMap<Url, Vec<Url>> = HashMap::new();
+    if path.exists() {
+      for record in Reader::from_path(&path)
+        .expect("Couldn't open cached images")
+        .deserialize::<ListingImage>()
+      {
+        let record = record.unwrap_or_else(|e| panic!("Couldn't deserialize image:{}", e));
+        let gallery = images.entry(record.url).or_default();
+        if record.position == 0 {
+          gallery.clear();
+        }
+        gallery.push(record.image);
+      }
+    }
+    let images_writer = WriterBuilder::new()
+      .has_headers(images.is_empty())
+      .from_writer(
+        OpenOptions::new()
+          .create(true)
+          .append(true)
+          .open(path)
+          .expect("Couldn't open images file"),
+      );
+
+    // Load up sellers, later records overriding earlier ones
+    let path = dir.join("sellers.csv");
+    let mut sellers = HashMap::new();
+    if path.exists() {
+      for record in Reader::from_path(&path)
+        .expect("Couldn't open cached sellers")
+        .deserialize::<Seller>()
+      {
+        let record = record.unwrap_or_else(|e| panic!("Couldn't deserialize seller:{}", e));
+        sellers.insert(record.id.clone(), record);
+      }
+    }
+    let sellers_writer = WriterBuilder::new()
+      .has_headers(sellers.is_empty())
+      .from_writer(
+        OpenOptions::new()
+          .create(true)
+          .append(true)
+          .open(path)
+          .expect("Couldn't open sellers file"),
+      );
+
     Self {
       listings,
       urls,
       writers,
+      validators,
+      validators_writer,
+      descriptions,
+      descriptions_writer,
+      images,
+      images_writer,
+      sellers,
+      sellers_writer,
+    }
+  }
+}
+
+/// Names of the files a cache is kept in
+pub fn files() -> Vec<String> {
+  Kind::all()
+    .iter()
+    .map(|kind| format!("{:?}.csv", kind))
+    .chain(
+      [
+        "validators.csv",
+        "descriptions.csv",
+        "images.csv",
+        "sellers.csv",
+      ]
+      .iter()
+      .map(|file| file.to_string()),
+    )
+    .collect()
+}
+
+/// Brings a cache file written by an older version up to the current columns
+fn migrate(path: &Path) -> Result<(), Error> {
+  add_lifecycle_columns(path)?;
+  add_empty_columns(
+    path,
+    &[
+      "posted_at",
+      "views",
+      "title",
+      "lat",
+      "lon",
+      "precision",
+      "seller_id",
+    ],
+  )
+}
+
+/// Adds the lifecycle columns to a cache file written before they existed, treating every record in
+/// it as a sighting of a live listing
+fn add_lifecycle_columns(path: &Path) -> Result<(), Error> {
+  let mut reader = Reader::from_path(path).map_err(Error::from)?;
+  let headers = reader.headers().map_err(Error::from)?.clone();
+  if headers.is_empty() || headers.iter().any(|header| header == "status") {
+    return Ok(());
+  }
+  let column = |name| {
+    headers
+      .iter()
+      .position(|header| header == name)
+      .ok_or_else(|| Error::from(format!("No '{}' column", name)))
+  };
+  let (url_column, timestamp_column) = (column("url")?, column("timestamp")?);
+  let records = reader
+    .records()
+    .collect::<Result<Vec<StringRecord>, _>>()
+    .map_err(Error::from)?;
+
+  // A listing was first seen at its earliest record
+  let mut first_seen: HashMap<&str, (DateTime<Utc>, &str)> = HashMap::new();
+  for record in records.iter() {
+    let timestamp = &record[timestamp_column];
+    let parsed = DateTime::<Utc>::from_str(timestamp).map_err(Error::from)?;
+    let first = first_seen
+      .entry(&record[url_column])
+      .or_insert((parsed, timestamp));
+    if parsed < first.0 {
+      *first = (parsed, timestamp);
     }
   }
+
+  let tmp_path = path.with_extension("tmp");
+  let mut writer = Writer::from_path(&tmp_path).map_err(Error::from)?;
+  let mut new_headers = headers.clone();
+  new_headers.extend(&["status", "first_seen", "last_seen"]);
+  writer.write_record(&new_headers).map_err(Error::from)?;
+  for record in records.iter() {
+    let mut record = record.clone();
+    let first = first_seen[&record[url_column]].1.to_string();
+    let last = record[timestamp_column].to_string();
+    record.extend(&["Active", &first, &last]);
+    writer.write_record(&record).map_err(Error::from)?;
+  }
+  writer.flush().map_err(Error::from)?;
+  rename(tmp_path, path).map_err(Error::from)?;
+
+  info!("Added lifecycle columns to {}", path.display());
+  Ok(())
+}
+
+/// Adds `columns` to a cache file written before they existed, leaving them empty in every record
+fn add_empty_columns(path: &Path, columns: &[&str]) -> Result<(), Error> {
+  let mut reader = Reader::from_path(path).map_err(Error::from)?;
+  let headers = reader.headers().map_err(Error::from)?.clone();
+  let missing: Vec<&str> = columns
+    .iter()
+    .filter(|column| !headers.iter().any(|header| header == **column))
+    .copied()
+    .collect();
+  if headers.is_empty() || missing.is_empty() {
+    return Ok(());
+  }
+
+  let tmp_path = path.with_extension("tmp");
+  let mut writer = Writer::from_path(&tmp_path).map_err(Error::from)?;
+  let mut new_headers = headers.clone();
+  new_headers.extend(&missing);
+  writer.write_record(&new_headers).map_err(Error::from)?;
+  for record in reader.records() {
+    let mut record = record.map_err(Error::from)?;
+    record.extend(missing.iter().map(|_| ""));
+    writer.write_record(&record).map_err(Error::from)?;
+  }
+  writer.flush().map_err(Error::from)?;
+  rename(tmp_path, path).map_err(Error::from)?;
+
+  info!("Added {} columns to {}", missing.join(", "), path.display());
+  Ok(())
 }
@@ -0,0 +1,477 @@
+use chrono::{DateTime, Utc};
+use reqwest::Url;
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{create_dir_all, read_to_string, remove_dir_all, rename, File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::engine::{WalkProgress, WalkStep};
+use crate::error::Error;
+use crate::io::data_path;
+
+/// The on-disk frontier of a crawl under `~/.truffles/crawl`
+///
+/// `roots.txt` holds the search roots of the crawl, while `progress.log` is appended to as result
+/// pages are found (`page`) and walked (`found` for every listing URL, then `result` for the
+/// page itself), as the walk of a search root makes it to its last page (`walked`) and as listings
+/// are done with (`listing`). The walk of a search root also records the numbered pages its first
+/// page tells of (`numbered`) and the latest link to a next page (`next`), so that a resumed crawl
+/// picks it up where it stopped. Crawls through sitemaps have no search roots. They record sitemap
+/// files as they're found (`sitemap`) and read (`lastmod` for every listing modification time, then
+/// `found` and `result` as for result pages).
+pub struct Checkpoint {
+  dir: PathBuf,
+  log: File,
+  roots: Option<Vec<Url>>,
+  walked: HashSet<Url>,
+  /// Numbered result pages and the latest link to a next page, by search root
+  numbered: HashMap<Url, Vec<Url>>,
+  next: HashMap<Url, Url>,
+  /// Result pages in the order they were come across, and as a set
+  result_urls: Vec<Url>,
+  pages: HashSet<Url>,
+  /// Sitemap files in the order they were come across, and as a set
+  sitemap_urls: Vec<Url>,
+  sitemaps: HashSet<Url>,
+  done_results: HashSet<Url>,
+  found: HashSet<Url>,
+  lastmods: HashMap<Url, DateTime<Utc>>,
+  done_listings: HashSet<Url>,
+}
+
+impl Checkpoint {
+  /// Starts a fresh checkpoint, discarding any previous one
+  pub fn start() -> Result<Self, Error> {
+    let dir = data_path("crawl");
+    if dir.exists() {
+      remove_dir_all(&dir).map_err(Error::from)?;
+    }
+    create_dir_all(&dir).map_err(Error::from)?;
+    Self::open(dir)
+  }
+
+  /// Picks up the checkpoint left behind by an unfinished crawl
+  pub fn resume() -> Result<Self, Error> {
+    let dir = data_path("crawl");
+    if !dir.exists() {
+      return Err(Error::from("There is no unfinished crawl to resume"));
+    }
+    Self::open(dir)
+  }
+
+  fn open(dir: PathBuf) -> Result<Self, Error> {
+    let roots = match read_to_string(dir.join("roots.txt")) {
+      Ok(content) => Some(parse_urls(content.lines())?),
+      Err(_) => None,
+    };
+
+    // A crawl killed mid-write may leave a partial last line behind, which may still read as a
+    // valid URL, so only lines that made it to their newline count
+    let log_path = dir.join("progress.log");
+    let content = read_to_string(&log_path).unwrap_or_default();
+    let complete = &content[..content.rfind('\n').map(|end| end + 1).unwrap_or(0)];
+
+    let mut result_urls = vec![];
+    let mut pages = HashSet::new();
+    let mut sitemap_urls = vec![];
+    let mut sitemaps = HashSet::new();
+    let mut walked = HashSet::new();
+    let mut numbered: HashMap<Url, Vec<Url>> = HashMap::new();
+    let mut next = HashMap::new();
+    let mut done_results = HashSet::new();
+    let mut found = HashSet::new();
+    let mut lastmods = HashMap::new();
+    let mut done_listings = HashSet::new();
+    for line in complete.lines() {
+      // Result pages and sitemaps are kept in the order they were found
+      if let Some(Ok(url)) = line.strip_prefix("page ").map(Url::from_str) {
+        if pages.insert(url.clone()) {
+          result_urls.push(url);
+        }
+        continue;
+      }
+      if let Some(Ok(url)) = line.strip_prefix("sitemap ").map(Url::from_str) {
+        if sitemaps.insert(url.clone()) {
+          sitemap_urls.push(url);
+        }
+        continue;
+      }
+      if let Some((kind, root, url)) = line.split_once(' ').and_then(|(kind, rest)| {
+        let (root, url) = rest.split_once(' ')?;
+        Some((kind, Url::from_str(root).ok()?, Url::from_str(url).ok()?))
+      }) {
+        match kind {
+          "numbered" => numbered.entry(root).or_default().push(url),
+          "next" => {
+            next.insert(root, url);
+          }
+          _ => (),
+        }
+        continue;
+      }
+      if let Some((url, lastmod)) = line
+        .strip_prefix("lastmod ")
+        .and_then(|rest| rest.split_once(' '))
+      {
+        if let (Ok(url), Ok(lastmod)) = (Url::from_str(url), DateTime::parse_from_rfc3339(lastmod))
+        {
+          lastmods.insert(url, lastmod.with_timezone(&Utc));
+        }
+        continue;
+      }
+      let (set, url) = match line.split_once(' ') {
+        Some(("walked", url)) => (&mut walked, url),
+        Some(("result", url)) => (&mut done_results, url),
+        Some(("found", url)) => (&mut found, url),
+        Some(("listing", url)) => (&mut done_listings, url),
+        _ => continue,
+      };
+      if let Ok(url) = Url::from_str(url) {
+        set.insert(url);
+      }
+    }
+
+    // The partial line is cut off, so that what gets appended next starts on a line of its own
+    let log = OpenOptions::new()
+      .create(true)
+      .append(true)
+      .open(&log_path)
+      .map_err(Error::from)?;
+    if complete.len() < content.len() {
+      log.set_len(complete.len() as u64).map_err(Error::from)?;
+    }
+
+    Ok(Self {
+      dir,
+      log,
+      roots,
+      walked,
+      numbered,
+      next,
+      result_urls,
+      pages,
+      sitemap_urls,
+      sitemaps,
+      done_results,
+      found,
+      lastmods,
+      done_listings,
+    })
+  }
+
+  /// The search roots of the crawl, if they were set before it was interrupted
+  pub fn roots(&self) -> Option<&Vec<Url>> {
+    self.roots.as_ref()
+  }
+
+  pub fn set_roots(&mut self, urls: &[Url]) -> Result<(), Error> {
+    let tmp_path = self.dir.join("roots.tmp");
+    let mut file = File::create(&tmp_path).map_err(Error::from)?;
+    for url in urls.iter() {
+      writeln!(file, "{}", url).map_err(Error::from)?;
+    }
+    file.sync_all().map_err(Error::from)?;
+    rename(tmp_path, self.dir.join("roots.txt")).map_err(Error::from)?;
+    self.roots = Some(urls.to_vec());
+    Ok(())
+  }
+
+  /// Search roots whose walk hasn't made it to their last result page yet
+  pub fn pending_roots(&self) -> Vec<Url> {
+    self
+      .roots
+      .iter()
+      .flatten()
+      .filter(|url| !self.walked.contains(url))
+      .cloned()
+      .collect()
+  }
+
+  pub fn root_walked(&mut self, url: &Url) -> Result<(), Error> {
+    writeln!(self.log, "walked {}", url).map_err(Error::from)?;
+    self.walked.insert(url.clone());
+    Ok(())
+  }
+
+  /// How far the walk of a search root got, leaving out the pages that were walked
+  pub fn walk_progress(&self, root: &Url) -> WalkProgress {
+    let is_left = |url: &&Url| !self.done_results.contains(*url);
+    WalkProgress {
+      started: self.done_results.contains(root),
+      pages: self
+        .numbered
+        .get(root)
+        .into_iter()
+        .flatten()
+        .filter(is_left)
+        .cloned()
+        .collect(),
+      next: self.next.get(root).filter(is_left).cloned(),
+    }
+  }
+
+  /// Records a step of the walk of a search root
+  pub fn walk_step(&mut self, root: &Url, step: &WalkStep) -> Result<(), Error> {
+    match step {
+      WalkStep::Numbered(urls) => {
+        for url in urls.iter() {
+          writeln!(self.log, "numbered {} {}", root, url).map_err(Error::from)?;
+        }
+        self.numbered.insert(root.clone(), urls.clone());
+        Ok(())
+      }
+      WalkStep::Next(url) => {
+        writeln!(self.log, "next {} {}", root, url).map_err(Error::from)?;
+        self.next.insert(root.clone(), url.clone());
+        Ok(())
+      }
+      WalkStep::Page(url, listing_urls) => {
+        self.add_result_url(url)?;
+        // Result pages that couldn't be fetched are left pending for a resumed crawl to pick up
+        match listing_urls {
+          Some(listing_urls) => self.result_done(url, listing_urls),
+          None => Ok(()),
+        }
+      }
+      WalkStep::Finished => self.root_walked(root),
+    }
+  }
+
+  /// Records a result page as soon as it's found, before it's walked
+  pub fn add_result_url(&mut self, url: &Url) -> Result<(), Error> {
+    if self.pages.insert(url.clone()) {
+      writeln!(self.log, "page {}", url).map_err(Error::from)?;
+      self.result_urls.push(url.clone());
+    }
+    Ok(())
+  }
+
+  /// Result page URLs that haven't been walked yet
+  pub fn pending_result_urls(&self) -> Vec<Url> {
+    self
+      .result_urls
+      .iter()
+      .filter(|url| !self.done_results.contains(url))
+      .cloned()
+      .collect()
+  }
+
+  /// Records a sitemap file as soon as it's found, before it's read, telling whether it's new
+  pub fn add_sitemap(&mut self, url: &Url) -> Result<bool, Error> {
+    if !self.sitemaps.insert(url.clone()) {
+      return Ok(false);
+    }
+    writeln!(self.log, "sitemap {}", url).map_err(Error::from)?;
+    self.sitemap_urls.push(url.clone());
+    Ok(true)
+  }
+
+  /// Sitemap files that haven't been read yet
+  pub fn pending_sitemaps(&self) -> Vec<Url> {
+    self
+      .sitemap_urls
+      .iter()
+      .filter(|url| !self.done_results.contains(url))
+      .cloned()
+      .collect()
+  }
+
+  /// Records the listings a sitemap file lists, along with when they were last modified
+  pub fn sitemap_done(
+    &mut self,
+    url: &Url,
+    listing_urls: &[(Url, Option<DateTime<Utc>>)],
+  ) -> Result<(), Error> {
+    for (listing_url, lastmod) in listing_urls.iter() {
+      if let Some(lastmod) = lastmod {
+        writeln!(self.log, "lastmod {} {}", listing_url, lastmod.to_rfc3339())
+          .map_err(Error::from)?;
+        self.lastmods.insert(listing_url.clone(), *lastmod);
+      }
+    }
+    let listing_urls: Vec<Url> = listing_urls.iter().map(|(url, _)| url.clone()).collect();
+    self.result_done(url, &listing_urls)
+  }
+
+  /// When the listings found in sitemaps were last modified, for the ones the sitemaps tell
+  pub fn lastmods(&self) -> &HashMap<Url, DateTime<Utc>> {
+    &self.lastmods
+  }
+
+  /// Listing URLs found on the result pages walked so far
+  pub fn found_listing_urls(&self) -> &HashSet<Url> {
+    &self.found
+  }
+
+  pub fn result_done(&mut self, url: &Url, listing_urls: &[Url]) -> Result<(), Error> {
+    for listing_url in listing_urls.iter() {
+      writeln!(self.log, "found {}", listing_url).map_err(Error::from)?;
+      self.found.insert(listing_url.clone());
+    }
+    writeln!(self.log, "result {}", url).map_err(Error::from)?;
+    self.done_results.insert(url.clone());
+    Ok(())
+  }
+
+  pub fn is_listing_done(&self, url: &Url) -> bool {
+    self.done_listings.contains(url)
+  }
+
+  pub fn listing_done(&mut self, url: &Url) -> Result<(), Error> {
+    writeln!(self.log, "listing {}", url).map_err(Error::from)?;
+    self.done_listings.insert(url.clone());
+    Ok(())
+  }
+
+  /// Whether every search root of the crawl has been walked to its end, and every result page along
+  /// the way and every sitemap has been fetched
+  pub fn is_complete(&self) -> bool {
+    self.roots.is_some()
+      && self.pending_roots().is_empty()
+      && self.pending_result_urls().is_empty()
+      && self.pending_sitemaps().is_empty()
+  }
+
+  /// Removes the checkpoint once the crawl has run to completion
+  pub fn finish(self) -> Result<(), Error> {
+    remove_dir_all(&self.dir).map_err(Error::from)
+  }
+}
+
+fn parse_urls<'a>(lines: impl Iterator<Item = &'a str>) -> Result<Vec<Url>, Error> {
+  lines
+    .filter(|line| !line.trim().is_empty())
+    .map(|line| Url::from_str(line.trim()).map_err(Error::from))
+    .collect()
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use chrono::TimeZone;
+  use std::env::temp_dir;
+  use std::fs::write;
+
+  #[test]
+  fn checkpoint_partial_line() {
+    let dir = temp_dir().join(format!("truffles-checkpoint-{}", std::process::id()));
+    create_dir_all(&dir).unwrap();
+    write(
+      dir.join("progress.log"),
+      "found https://www.bazaraki.com/adv/1_a/\nlisting https://www.bazaraki.com/adv/1_a/\nlisting https://www.bazaraki.com/adv/2",
+    )
+    .unwrap();
+
+    let url = |s| Url::from_str(s).unwrap();
+    let mut checkpoint = Checkpoint::open(dir.clone()).unwrap();
+    assert!(checkpoint.is_listing_done(&url("https://www.bazaraki.com/adv/1_a/")));
+    assert!(!checkpoint.is_listing_done(&url("https://www.bazaraki.com/adv/2")));
+
+    // What comes next doesn't run into the partial line
+    checkpoint
+      .listing_done(&url("https://www.bazaraki.com/adv/2_b/"))
+      .unwrap();
+    let checkpoint = Checkpoint::open(dir.clone()).unwrap();
+    assert!(checkpoint.is_listing_done(&url("https://www.bazaraki.com/adv/2_b/")));
+    assert_eq!(checkpoint.done_listings.len(), 2);
+    checkpoint.finish().unwrap();
+  }
+
+  #[test]
+  fn checkpoint_roots() {
+    let dir = temp_dir().join(format!("truffles-checkpoint-roots-{}", std::process::id()));
+    create_dir_all(&dir).unwrap();
+    let url = |s| Url::from_str(s).unwrap();
+    let (a, b) = (
+      url("https://www.bazaraki.com/a/"),
+      url("https://www.bazaraki.com/b/"),
+    );
+
+    let mut checkpoint = Checkpoint::open(dir.clone()).unwrap();
+    checkpoint.set_roots(&[a.clone(), b.clone()]).unwrap();
+    checkpoint.add_result_url(&a).unwrap();
+    checkpoint.add_result_url(&b).unwrap();
+    checkpoint.result_done(&a, &[]).unwrap();
+    checkpoint.result_done(&b, &[]).unwrap();
+    checkpoint.root_walked(&a).unwrap();
+    // A root whose walk didn't make it to the end keeps the crawl from being complete, even though
+    // none of its result pages are pending
+    assert_eq!(checkpoint.pending_roots(), vec![b.clone()]);
+    assert!(!checkpoint.is_complete());
+
+    // The walk of the root is picked up past the pages it got through
+    let (b2, b3) = (
+      url("https://www.bazaraki.com/b/2"),
+      url("https://www.bazaraki.com/b/3"),
+    );
+    let steps = [
+      WalkStep::Numbered(vec![b2.clone(), b3.clone()]),
+      WalkStep::Next(b2.clone()),
+      WalkStep::Page(b2.clone(), Some(vec![])),
+      WalkStep::Next(b3.clone()),
+    ];
+    for step in steps.iter() {
+      checkpoint.walk_step(&b, step).unwrap();
+    }
+
+    let mut checkpoint = Checkpoint::open(dir.clone()).unwrap();
+    assert_eq!(checkpoint.pending_roots(), vec![b.clone()]);
+    assert_eq!(
+      checkpoint.walk_progress(&b),
+      WalkProgress {
+        started: true,
+        pages: vec![b3.clone()],
+        next: Some(b3),
+      }
+    );
+    assert_eq!(
+      checkpoint.walk_progress(&a),
+      WalkProgress {
+        started: true,
+        ..WalkProgress::default()
+      }
+    );
+    checkpoint.root_walked(&b).unwrap();
+    assert!(checkpoint.is_complete());
+    checkpoint.finish().unwrap();
+  }
+
+  #[test]
+  fn checkpoint_sitemaps() {
+    let dir = temp_dir().join(format!(
+      "truffles-checkpoint-sitemaps-{}",
+      std::process::id()
+    ));
+    create_dir_all(&dir).unwrap();
+    let url = |s| Url::from_str(s).unwrap();
+    let (index, a, b) = (
+      url("https://www.bazaraki.com/sitemap.xml"),
+      url("https://www.bazaraki.com/sitemap-1.xml"),
+      url("https://www.bazaraki.com/sitemap-2.xml"),
+    );
+    let listing = url("https://www.bazaraki.com/adv/1_a/");
+    let lastmod = Utc.with_ymd_and_hms(2021, 3, 1, 10, 30, 0).unwrap();
+
+    let mut checkpoint = Checkpoint::open(dir.clone()).unwrap();
+    checkpoint.set_roots(&[]).unwrap();
+    assert!(checkpoint.add_sitemap(&index).unwrap());
+    checkpoint.result_done(&index, &[]).unwrap();
+    assert!(checkpoint.add_sitemap(&a).unwrap());
+    assert!(checkpoint.add_sitemap(&b).unwrap());
+    assert!(!checkpoint.add_sitemap(&a).unwrap());
+    checkpoint
+      .sitemap_done(&a, &[(listing.clone(), Some(lastmod))])
+      .unwrap();
+
+    // A sitemap that wasn't read is left pending, and listings keep their modification times
+    let checkpoint = Checkpoint::open(dir.clone()).unwrap();
+    assert_eq!(checkpoint.pending_sitemaps(), vec![b.clone()]);
+    assert!(checkpoint.pending_result_urls().is_empty());
+    assert!(!checkpoint.is_complete());
+    assert!(checkpoint.found_listing_urls().contains(&listing));
+    assert_eq!(checkpoint.lastmods().get(&listing), Some(&lastmod));
+    checkpoint.finish().unwrap();
+  }
+}
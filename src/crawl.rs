@@ -0,0 +1,620 @@
+use chrono::Utc;
+use futures::StreamExt;
+use indicatif::{ProgressBar, ProgressStyle};
+use reqwest::Url;
+use structopt::StructOpt;
+
+use std::collections::{BTreeMap, HashSet};
+use std::env::temp_dir;
+use std::fs::remove_dir_all;
+use std::mem::take;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::area::Area;
+use crate::cache::Cache;
+use crate::checkpoint::Checkpoint;
+use crate::engine::{Backend, Engine, ListingOutcome, WalkProgress, WalkStep, DEFAULT_CONCURRENCY};
+use crate::images::ImageStore;
+use crate::io::set_data_dir;
+use crate::limits::Limits;
+use crate::listing::{Kind, Listing};
+use crate::profile::{Profile, ProfileArgs};
+use crate::replay::Recording;
+use crate::run::Run;
+use crate::site::Website;
+use crate::sitemap::Sitemap;
+use crate::status::Status;
+use crate::throttle::Budget;
+
+#[derive(Debug, StructOpt)]
+pub struct CrawlArgs {
+  #[structopt(
+    short = "a",
+    long = "area",
+    help = "Only fetch listings in this area [options: famagusta|larnaka|lefkosia|limassol|paphos|attica|crete|thessaloniki|berlin|hamburg|munich]"
+  )]
+  pub area: Option<Area>,
+  #[structopt(
+    short = "s",
+    long = "site",
+    help = "The website to fetch listings from. Defaults to bazaraki [options: bazaraki]"
+  )]
+  pub site: Option<Website>,
+  #[structopt(
+    short = "f",
+    long = "force",
+    help = "Fetch all listings regardless of their latest timestamp"
+  )]
+  pub force: bool,
+  #[structopt(
+    short = "k",
+    long = "kind",
+    help = "Only fetch listings of a specific kind [options: plot|property]"
+  )]
+  pub kind: Option<Kind>,
+
+  #[structopt(
+    short = "t",
+    long = "throttle",
+    help = "An interval (in milliseconds) to wait for between HTTP requests to the same host. Defaults to 1000ms"
+  )]
+  pub throttling: Option<u64>,
+
+  #[structopt(
+    short = "b",
+    long = "burst",
+    help = "The number of HTTP requests that can be sent to the same host back-to-back. Defaults to 1"
+  )]
+  pub burst: Option<u32>,
+
+  #[structopt(
+    short = "c",
+    long = "concurrency",
+    help = "The maximum number of HTTP requests in flight at any time. Defaults to 4"
+  )]
+  pub concurrency: Option<usize>,
+
+  #[structopt(
+    long = "max-result-pages",
+    help = "Stop the crawl after fetching this many result pages"
+  )]
+  pub max_result_pages: Option<usize>,
+
+  #[structopt(
+    long = "max-listings",
+    help = "Stop the crawl after fetching this many listings"
+  )]
+  pub max_listings: Option<usize>,
+
+  #[structopt(
+    long = "max-time",
+    help = "Stop the crawl after running for this many seconds"
+  )]
+  pub max_time: Option<u64>,
+
+  #[structopt(
+    long = "max-bytes",
+    help = "Stop the crawl after downloading this many bytes"
+  )]
+  pub max_bytes: Option<u64>,
+
+  #[structopt(
+    long = "ignore-robots",
+    help = "Fetch pages regardless of what the sites' robots.txt allow"
+  )]
+  pub ignore_robots: bool,
+
+  #[structopt(
+    short = "i",
+    long = "incremental",
+    help = "Walk result pages newest first and stop at the first page of known, fresh listings"
+  )]
+  pub incremental: bool,
+
+  #[structopt(
+    long = "sitemaps",
+    conflicts_with_all = &["area", "kind", "incremental"],
+    help = "Find listings through the site's sitemaps instead of its result pages, and only fetch the ones modified since they were cached"
+  )]
+  pub sitemaps: bool,
+
+  #[structopt(
+    long = "download-images",
+    help = "Download the images of every listing parsed into ~/.truffles/images"
+  )]
+  pub download_images: bool,
+
+  #[structopt(
+    short = "r",
+    long = "resume",
+    help = "Pick up an interrupted crawl from where it stopped"
+  )]
+  pub resume: bool,
+
+  #[structopt(
+    long = "replay",
+    parse(from_os_str),
+    conflicts_with = "record",
+    help = "Serve every request from a directory of recorded responses instead of the network, keeping the crawl's data in a scratch directory rather than ~/.truffles"
+  )]
+  pub replay: Option<PathBuf>,
+
+  #[structopt(
+    long = "record",
+    parse(from_os_str),
+    help = "Record every response into a directory, so the crawl can be replayed later"
+  )]
+  pub record: Option<PathBuf>,
+
+  #[structopt(flatten)]
+  pub profile: ProfileArgs,
+}
+
+/// How old a cached listing gets before it's fetched again
+const STALE_AFTER_DAYS: i64 = 30;
+
+/// Where replays keep their data, away from the real cache
+const REPLAY_DATA_DIR: &str = "truffles-replay";
+
+pub async fn crawl(args: CrawlArgs) -> Result<(), String> {
+  // Replays never touch the real data. They start afresh unless resumed.
+  if args.replay.is_some() {
+    let dir = temp_dir().join(REPLAY_DATA_DIR);
+    if dir.exists() && !args.resume {
+      remove_dir_all(&dir).map_err(|e| format!("Couldn't clear replay data:{}", e))?;
+    }
+    set_data_dir(dir.clone()).map_err(|e| format!("Couldn't set replay data directory:{}", e))?;
+    println!(
+      "Replaying into {}, the real data is left alone",
+      dir.display()
+    );
+  }
+
+  // Initial engine
+  let backend = match (&args.replay, &args.record) {
+    (Some(dir), _) => {
+      Backend::Replay(Recording::open(dir).map_err(|e| format!("Couldn't open recording:{}", e))?)
+    }
+    (None, Some(dir)) => Backend::Record(
+      Recording::create(dir).map_err(|e| format!("Couldn't create recording:{}", e))?,
+    ),
+    (None, None) => Backend::Live,
+  };
+  let profile = Profile::load()
+    .and_then(|profile| profile.with_args(&args.profile))
+    .map_err(|e| format!("Couldn't set up client profile:{}", e))?;
+  let engine = Engine::new(
+    Budget::new(args.burst, args.throttling.map(Duration::from_millis)),
+    args.concurrency.unwrap_or(DEFAULT_CONCURRENCY),
+    backend,
+    profile,
+    Limits {
+      result_pages: args.max_result_pages,
+      listings: args.max_listings,
+      time: args.max_time.map(Duration::from_secs),
+      bytes: args.max_bytes,
+    },
+    args.ignore_robots,
+  )
+  .map_err(|e| format!("Couldn't start engine:{}", e))?;
+
+  if args.ignore_robots {
+    println!(
+      "WARNING: Ignoring robots.txt, pages will be fetched whether the sites allow it or not"
+    );
+  }
+
+  // Load cache
+  let mut cache = Cache::load();
+
+  // Load or start the crawl's checkpoint
+  let checkpoint = if args.resume {
+    Checkpoint::resume()
+  } else {
+    Checkpoint::start()
+  }
+  .map_err(|e| format!("Couldn't set up crawl checkpoint:{}", e))?;
+
+  // What the search covers
+  let site = args.site.clone().unwrap_or(Website::Bazaraki);
+  let (area, kind) = (args.area.clone(), args.kind.clone());
+  let mut run = Run::start(&site, &area, &kind);
+  let crawled = crawl_search(&args, &engine, &mut cache, checkpoint, &mut run).await;
+
+  // Record and report the run, even one that failed part way through
+  let (requests, bytes) = engine.traffic();
+  run.requests = requests;
+  run.replayed = engine.replays();
+  run.bytes = bytes;
+  run.stopped = engine.stopped();
+  run.error = crawled.as_ref().err().cloned();
+  run.finished = Utc::now();
+  run.summarise();
+  let saved = run
+    .save()
+    .map_err(|e| format!("Couldn't save run record:{}", e));
+
+  crawled.and(saved)
+}
+
+/// Crawls the search the run covers, from its result pages to its listings
+async fn crawl_search(
+  args: &CrawlArgs,
+  engine: &Engine,
+  cache: &mut Cache,
+  mut checkpoint: Checkpoint,
+  run: &mut Run,
+) -> Result<(), String> {
+  let (site, area, kind) = (run.site.clone(), run.area.clone(), run.kind.clone());
+
+  // Read sitemaps, if asked to, one level of sitemap indexes at a time. A resumed crawl reads the
+  // ones it didn't get to, or couldn't read, again.
+  let now = Utc::now();
+  let stage = Instant::now();
+  let mut sitemap_urls = checkpoint.pending_sitemaps();
+  if checkpoint.roots().is_none() && args.sitemaps {
+    for url in engine.sitemap_roots(&site).await.into_iter() {
+      if checkpoint
+        .add_sitemap(&url)
+        .map_err(|e| format!("Couldn't save crawl progress:{}", e))?
+      {
+        sitemap_urls.push(url);
+      }
+    }
+    // There are no search roots to walk
+    checkpoint
+      .set_roots(&[])
+      .map_err(|e| format!("Couldn't save search roots:{}", e))?;
+  }
+  if !sitemap_urls.is_empty() {
+    let bar = ProgressBar::new_spinner();
+    bar.set_style(
+      ProgressStyle::default_spinner()
+        .template("{spinner} Reading sitemaps      ... {pos} sitemaps")
+        .tick_chars("|/-\\-"),
+    );
+    bar.enable_steady_tick(250);
+    let mut n_sitemaps = 0;
+    while !sitemap_urls.is_empty() {
+      let sitemaps = engine.read_sitemaps(&site, take(&mut sitemap_urls)).await;
+      for (sitemap_url, sitemap) in sitemaps.into_iter() {
+        match sitemap {
+          Some(Sitemap::Index(children)) => {
+            for child in children.into_iter() {
+              if checkpoint
+                .add_sitemap(&child)
+                .map_err(|e| format!("Couldn't save crawl progress:{}", e))?
+              {
+                sitemap_urls.push(child);
+              }
+            }
+            checkpoint
+              .result_done(&sitemap_url, &[])
+              .map_err(|e| format!("Couldn't save crawl progress:{}", e))?;
+          }
+          Some(Sitemap::UrlSet(urls)) => {
+            checkpoint
+              .sitemap_done(&sitemap_url, &urls)
+              .map_err(|e| format!("Couldn't save crawl progress:{}", e))?;
+            n_sitemaps += 1;
+            run.result_pages += 1;
+          }
+          // Sitemaps that couldn't be read are left pending for a resumed crawl to pick up
+          None => (),
+        }
+        bar.inc(1);
+      }
+    }
+    bar.finish();
+    println!(
+      "{:>6} listings in {} sitemaps of {}",
+      checkpoint.found_listing_urls().len(),
+      n_sitemaps,
+      site
+    );
+  }
+
+  // Walk the result pages of every search root, up to the first one without anything new if
+  // incremental. A resumed crawl picks up the walks of the roots it didn't get to the end of, past
+  // the pages it already walked.
+  let roots: Vec<(Url, WalkProgress)> = match checkpoint.roots() {
+    Some(_) => checkpoint
+      .pending_roots()
+      .into_iter()
+      .map(|root| {
+        let progress = checkpoint.walk_progress(&root);
+        (root, progress)
+      })
+      .collect(),
+    None => {
+      let roots = engine
+        .search_roots(&site, area.clone(), kind.clone())
+        .map_err(|e| format!("Couldn't get search roots:{}", e))?;
+      checkpoint
+        .set_roots(&roots)
+        .map_err(|e| format!("Couldn't save search roots:{}", e))?;
+      roots
+        .into_iter()
+        .map(|root| (root, WalkProgress::default()))
+        .collect()
+    }
+  };
+  if !roots.is_empty() {
+    let bar = ProgressBar::new_spinner();
+    bar.set_style(
+      ProgressStyle::default_spinner()
+        .template("{spinner} Walking result pages  ... {pos} pages")
+        .tick_chars("|/-\\-"),
+    );
+    bar.enable_steady_tick(250);
+    let incremental = args.incremental;
+    let is_known = |url: &Url| {
+      incremental
+        && cache
+          .get_last_timestamp(url)
+          .map(|timestamp| (now - timestamp).num_days() < STALE_AFTER_DAYS)
+          .unwrap_or(false)
+    };
+    let mut walk = engine.walk_result_pages(&site, roots, &is_known);
+    let mut n_pages: BTreeMap<Url, usize> = BTreeMap::new();
+    while let Some((root, step)) = walk.next().await {
+      checkpoint
+        .walk_step(&root, &step)
+        .map_err(|e| format!("Couldn't save crawl progress:{}", e))?;
+      if let WalkStep::Page(_, urls) = step {
+        if urls.is_some() {
+          run.result_pages += 1;
+        }
+        *n_pages.entry(root).or_insert(0) += 1;
+        bar.inc(1);
+      }
+    }
+    bar.finish();
+    let pending: HashSet<Url> = checkpoint.pending_roots().into_iter().collect();
+    for (root, n) in n_pages.iter() {
+      if pending.contains(root) {
+        println!("{:>6} result pages of {} (walk unfinished)", n, root);
+      } else {
+        println!("{:>6} result pages of {}", n, root);
+      }
+    }
+  }
+  run.stage("result pages", stage.elapsed());
+
+  // Get listing URLs
+  let stage = Instant::now();
+  let result_urls = checkpoint.pending_result_urls();
+  let bar = ProgressBar::new(result_urls.len() as u64);
+  bar.set_style(
+    ProgressStyle::default_bar()
+      .template("{spinner} Getting listings URLs ... {percent:>3}% (ETA ~{eta})")
+      .tick_chars("|/-\\-"),
+  );
+  bar.enable_steady_tick(250);
+  let mut listing_url_stream = engine.get_all_listing_urls(result_urls);
+  while let Some((result_url, urls)) = listing_url_stream.next().await {
+    // Result pages that couldn't be fetched are left pending for a resumed crawl to pick up
+    if let Some(urls) = urls {
+      checkpoint
+        .result_done(&result_url, &urls)
+        .map_err(|e| format!("Couldn't save crawl progress:{}", e))?;
+      run.result_pages += 1;
+    }
+    bar.inc(1);
+  }
+  bar.finish();
+  run.stage("listing URLs", stage.elapsed());
+
+  let mut listing_urls: HashSet<Url> = checkpoint
+    .found_listing_urls()
+    .iter()
+    .filter(|url| !checkpoint.is_listing_done(url))
+    .cloned()
+    .collect();
+
+  // Only fetch "stale" listings, or the ones the sitemaps tell were modified since they were cached
+  if !args.force {
+    listing_urls.retain(|url| {
+      match (
+        cache.get_last_timestamp(url),
+        checkpoint.lastmods().get(url),
+      ) {
+        (Some(timestamp), Some(lastmod)) => *lastmod > timestamp,
+        (Some(timestamp), None) => (now - timestamp).num_days() >= STALE_AFTER_DAYS,
+        (None, _) => true,
+      }
+    });
+  }
+
+  // Get listing pages, parse them and cache the results
+  let stage = Instant::now();
+  let bar = ProgressBar::new(listing_urls.len() as u64);
+  bar.set_style(
+    ProgressStyle::default_bar()
+      .template("{spinner} Getting listings      ... {percent:>3}% (ETA ~{eta})")
+      .tick_chars("|/-\\-"),
+  );
+  bar.enable_steady_tick(250);
+  // Cached listings are revalidated rather than fetched in full, unless forced
+  let force = args.force;
+  let listings = listing_urls
+    .into_iter()
+    .map(|url| {
+      let validators = match cache.get_latest(&url) {
+        Some(latest) if !force && latest.status().is_live() => cache.get_validators(&url).cloned(),
+        _ => None,
+      };
+      (url, validators)
+    })
+    .collect();
+  let mut image_urls: Vec<Url> = vec![];
+  let mut listing_stream = engine.get_listings(listings);
+  while let Some((url, outcome)) = listing_stream.next().await {
+    if !matches!(outcome, ListingOutcome::Failed | ListingOutcome::Skipped) {
+      checkpoint
+        .listing_done(&url)
+        .map_err(|e| format!("Couldn't save crawl progress:{}", e))?;
+    }
+    match outcome {
+      ListingOutcome::Parsed(mut listing, validators) => {
+        match cache.get_latest(&url) {
+          Some(previous) => {
+            listing.follow(previous);
+            run.updated += 1;
+          }
+          None => run.new += 1,
+        }
+        image_urls.extend(listing.images().iter().cloned());
+        cache.add(*listing);
+        cache.set_validators(validators);
+      }
+      ListingOutcome::Unchanged(validators) => {
+        // Record that the listing is still live as of now
+        let mut listing = cache
+          .get_latest(&url)
+          .cloned()
+          .expect("INTERNAL ERROR: Revalidated a listing that isn't cached");
+        listing.seen(Utc::now());
+        cache.add(listing);
+        cache.set_validators(validators);
+        // Its gallery hasn't changed either, but may not have been downloaded yet
+        image_urls.extend(cache.get_images(&url).into_iter().flatten().cloned());
+        run.unchanged += 1;
+      }
+      ListingOutcome::Gone => {
+        let latest = cache
+          .get_latest(&url)
+          .filter(|latest| latest.status().is_live());
+        if let Some(mut listing) = latest.cloned() {
+          listing.end(Status::Removed, Utc::now());
+          cache.add(listing);
+        }
+        run.gone += 1;
+      }
+      ListingOutcome::Unparsable(err) => run.unparsable(&err),
+      ListingOutcome::Failed => run.failed += 1,
+      ListingOutcome::Skipped => (),
+    }
+    bar.inc(1);
+  }
+  bar.finish();
+  run.stage("listings", stage.elapsed());
+
+  // Download the images of the listings parsed or revalidated, if asked to
+  if args.download_images {
+    let stage = Instant::now();
+    let mut store = ImageStore::open().map_err(|e| format!("Couldn't open image store:{}", e))?;
+    let mut seen = HashSet::new();
+    image_urls.retain(|url| !store.contains(url) && seen.insert(url.clone()));
+    let bar = ProgressBar::new(image_urls.len() as u64);
+    bar.set_style(
+      ProgressStyle::default_bar()
+        .template("{spinner} Getting images        ... {percent:>3}% (ETA ~{eta})")
+        .tick_chars("|/-\\-"),
+    );
+    bar.enable_steady_tick(250);
+    let mut image_stream = engine.get_images(image_urls);
+    while let Some((url, body)) = image_stream.next().await {
+      if let Some(body) = body {
+        match store.put(&url, &body) {
+          Ok(_) => run.images += 1,
+          Err(e) => error!("Couldn't store image {}:{}", url, e),
+        }
+      }
+      bar.inc(1);
+    }
+    bar.finish();
+    run.stage("images", stage.elapsed());
+  }
+
+  // Live listings within the search that no longer show up in its results have expired. This is
+  // only safe to tell when every search root was walked to its last page, every result page made it
+  // through, and the search is known to be the one given on the command line, i.e. the crawl wasn't
+  // resumed.
+  if args.incremental {
+    info!("Incremental crawls only walk part of the results, so no listings were expired");
+  } else if args.sitemaps {
+    info!("Sitemaps needn't list every listing, so no listings were expired");
+  } else if engine.stopped().is_some() {
+    warn!("The crawl stopped early, so no listings were expired");
+  } else if !checkpoint.pending_roots().is_empty() {
+    warn!("Not every search root was walked to its last result page, so no listings were expired");
+  } else if args.resume || !checkpoint.pending_result_urls().is_empty() {
+    warn!("Not every result page of this crawl was fetched, so no listings were expired");
+  } else {
+    let found = checkpoint.found_listing_urls();
+    let expired: Vec<Listing> = cache
+      .latest_listings()
+      .filter(|listing| {
+        listing.status().is_live()
+          && listing.website() == &site
+          && area
+            .as_ref()
+            .map(|area| listing.area() == area)
+            .unwrap_or(true)
+          && kind
+            .as_ref()
+            .map(|kind| &listing.kind() == kind)
+            .unwrap_or(true)
+          && !found.contains(listing.url())
+      })
+      .cloned()
+      .collect();
+    let now = Utc::now();
+    for mut listing in expired.into_iter() {
+      listing.end(Status::Expired, now);
+      cache.add(listing);
+      run.expired += 1;
+    }
+  }
+
+  // A crawl that stopped early or failed to get some pages leaves its checkpoint behind, so it can
+  // be resumed
+  match engine.stopped() {
+    Some(reason) => println!(
+      "Stopped early, as the crawl {} (pick it up with --resume)",
+      reason
+    ),
+    None if !checkpoint.is_complete() || run.failed > 0 => {
+      println!("Some pages couldn't be fetched (retry them with --resume)")
+    }
+    None => checkpoint
+      .finish()
+      .map_err(|e| format!("Couldn't clean up crawl checkpoint:{}", e))?,
+  }
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn crawl_args_sitemaps() {
+    let parse = |args: &[&str]| CrawlArgs::from_iter_safe(args.iter());
+    assert!(parse(&["crawl", "--sitemaps"]).is_ok());
+    assert!(parse(&[
+      "crawl",
+      "--area",
+      "limassol",
+      "--kind",
+      "plot",
+      "--incremental"
+    ])
+    .is_ok());
+    // Sitemaps cover the whole site, and aren't walked incrementally
+    for arg in [
+      &["--area", "limassol"][..],
+      &["--kind", "plot"],
+      &["--incremental"],
+    ] {
+      assert!(
+        parse(&[&["crawl", "--sitemaps"], arg].concat()).is_err(),
+        "{:?}",
+        arg
+      );
+    }
+  }
+}
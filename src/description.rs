@@ -0,0 +1,25 @@
+use chrono::{DateTime, Utc};
+use reqwest::Url;
+
+use crate::io::{timestamp_deserializer, timestamp_serializer, url_deserializer, url_serializer};
+
+/// The description of a listing, as kept in `descriptions.csv` next to the cached listings
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Description {
+  /// UID of the listing
+  pub id: String,
+  /// URL
+  #[serde(
+    serialize_with = "url_serializer",
+    deserialize_with = "url_deserializer"
+  )]
+  pub url: Url,
+  /// Time the description was read
+  #[serde(
+    serialize_with = "timestamp_serializer",
+    deserialize_with = "timestamp_deserializer"
+  )]
+  pub timestamp: DateTime<Utc>,
+  /// Plain text, without markup and with whitespace normalised
+  pub text: String,
+}
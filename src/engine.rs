@@ -1,165 +1,1209 @@
-use log::LevelFilter;
-use reqwest::{Client, Url};
-use scraper::{Html, Selector};
-use simplelog::{ConfigBuilder, LevelPadding, WriteLogger};
+use chrono::{DateTime, Utc};
+use futures::stream::{self, Stream, StreamExt};
+use futures::{future, Future};
+use rand::Rng;
+use reqwest::header::{HeaderMap, IF_MODIFIED_SINCE, IF_NONE_MATCH, USER_AGENT};
+use reqwest::{Client, StatusCode, Url};
+use scraper::Html;
+use tokio::sync::Mutex;
+use tokio::task;
+use tokio::time::{delay_for, timeout};
 
-use std::fs::{create_dir_all, OpenOptions};
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::sync::Arc;
 use std::time::Duration;
 
+use crate::archive::{Archive, Page};
 use crate::area::Area;
+use crate::error::Error;
+use crate::limits::{Limits, Meter};
 use crate::listing::{Kind, Listing};
+use crate::profile::{CookieJar, Profile};
+use crate::quarantine::Quarantine;
+use crate::replay::Recording;
+use crate::robots::Robots;
 use crate::site::Website;
-use crate::throttle::Throttler;
-use crate::urls;
+use crate::sitemap::{self, Sitemap};
+use crate::sites::{Registry, SiteScraper};
+use crate::throttle::{parse_retry_after, Budget, RateLimiter};
+use crate::validators::Validators;
 
-const HEADER_KEY: &str = "User-Agent";
-const HEADER_VALUE:&str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/70.0.3538.77 Safari/537.36";
+/// Default number of in-flight requests
+pub const DEFAULT_CONCURRENCY: usize = 4;
+
+/// How many times a request is attempted before giving up on a transient failure
+const MAX_ATTEMPTS: u32 = 4;
+
+/// Base delay for exponential backoff between attempts
+const BACKOFF_BASE: Duration = Duration::from_secs(2);
+
+/// A site's `robots.txt`, which is `None` until it's been fetched
+type SiteRobots = Arc<Mutex<Option<Robots>>>;
+
+/// How a single HTTP request turned out
+#[derive(Debug)]
+pub enum Fetch {
+  /// The page was served successfully
+  Ok(String, Validators),
+  /// The page hasn't changed since it was last fetched
+  NotModified,
+  /// A listing page redirected somewhere that isn't a listing
+  Redirected(Url),
+  /// The site's `robots.txt` doesn't allow fetching the page
+  Disallowed,
+  /// The page doesn't exist (anymore)
+  NotFound(StatusCode),
+  /// The server refuses to serve the page to this client
+  Blocked(StatusCode),
+  /// The server rejected the request for some other reason
+  Rejected(StatusCode),
+  /// Something went wrong that might not go wrong again
+  Transient(String),
+}
+
+impl Fetch {
+  fn from_response(url: &Url, status: StatusCode, headers: &HeaderMap, text: String) -> Self {
+    if status.is_success() {
+      let validators = Validators::new(url, headers, &text);
+      Self::Ok(text, validators)
+    } else {
+      Self::from_status(status)
+    }
+  }
+
+  fn from_status(status: StatusCode) -> Self {
+    match status {
+      StatusCode::NOT_MODIFIED => Self::NotModified,
+      StatusCode::NOT_FOUND | StatusCode::GONE => Self::NotFound(status),
+      StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Self::Blocked(status),
+      // Too many requests is retried, once the host's Retry-After delay is over
+      StatusCode::REQUEST_TIMEOUT | StatusCode::TOO_MANY_REQUESTS => {
+        Self::Transient(format!("Got {}", status))
+      }
+      _ if status.is_server_error() => Self::Transient(format!("Got {}", status)),
+      _ => Self::Rejected(status),
+    }
+  }
+}
+
+impl fmt::Display for Fetch {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Ok(..) => write!(f, "OK"),
+      Self::NotModified => write!(f, "Not modified"),
+      Self::Redirected(url) => write!(f, "Redirected to {}", url),
+      Self::Disallowed => write!(f, "Disallowed by robots.txt"),
+      Self::NotFound(status) => write!(f, "Not found ({})", status),
+      Self::Blocked(status) => write!(f, "Blocked ({})", status),
+      Self::Rejected(status) => write!(f, "Rejected ({})", status),
+      Self::Transient(reason) => write!(f, "Transient failure ({})", reason),
+    }
+  }
+}
+
+/// What came out of trying to get a single listing
+#[derive(Debug)]
+pub enum ListingOutcome {
+  /// The listing was fetched and parsed
+  Parsed(Box<Listing>, Validators),
+  /// The listing is still up and hasn't changed since it was last fetched
+  Unchanged(Validators),
+  /// The listing has been taken down, as told by a 404/410 or a redirect away from it
+  Gone,
+  /// The listing was fetched but couldn't be parsed
+  Unparsable(Error),
+  /// The listing couldn't be fetched
+  Failed,
+  /// The listing wasn't fetched, as the crawl reached one of its limits or `robots.txt` disallows it
+  Skipped,
+}
+
+/// A step in the walk through the result pages of a search root
+#[derive(Debug)]
+pub enum WalkStep {
+  /// The numbered result pages the first page of the search root tells of
+  Numbered(Vec<Url>),
+  /// The link to the next page off of the result page that comes next
+  Next(Url),
+  /// A result page along with the listing URLs found on it, or `None` if it couldn't be fetched
+  Page(Url, Option<Vec<Url>>),
+  /// The walk made it to the last result page
+  Finished,
+}
+
+/// How far an earlier crawl got in the walk of a search root, for a resumed crawl to go on from
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WalkProgress {
+  /// Whether the first page was walked
+  pub started: bool,
+  /// Numbered result pages that are left to walk
+  pub pages: Vec<Url>,
+  /// The link to the next page off of the latest page walked, unless it was walked too
+  pub next: Option<Url>,
+}
+
+/// Where responses come from
+pub enum Backend {
+  /// Requests go out to the network
+  Live,
+  /// Responses are served from a recording, without touching the network
+  Replay(Recording),
+  /// Requests go out to the network and responses are added to a recording
+  Record(Recording),
+}
 
 pub struct Engine {
+  archive: Arc<Archive>,
+  backend: Backend,
   client: Client,
-  throttler: Throttler,
+  cookies: Option<CookieJar>,
+  profile: Profile,
+  quarantine: Quarantine,
+  limiter: RateLimiter,
+  registry: Registry,
+  /// The `robots.txt` of every site by root URL, unless it's ignored, each behind a lock of its own
+  /// so that fetching one only holds up requests to the same site
+  robots: Option<Mutex<HashMap<Url, SiteRobots>>>,
+  concurrency: usize,
+  meter: Meter,
 }
 
 impl Engine {
-  pub fn new(log_level: LevelFilter, throttling: Option<Duration>) -> Self {
-    let client = Client::new();
-    let throttler = Throttler::new(throttling);
-
-    let path = dirs::home_dir()
-      .expect("Couldn't get home directory")
-      .join(PathBuf::from(".truffles/truffles.log"));
-
-    if !path.exists() {
-      create_dir_all(
-        path
-          .parent()
-          .expect("INTERNAL ERROR: Can't get path's parent"),
-      )
-      .expect("INTERNAL ERROR: Can't create directory");
-    }
-
-    let log_file = OpenOptions::new()
-      .create(true)
-      .append(true)
-      .open(path)
-      .expect(&format!("Couldn't open log file"));
-
-    let _ = WriteLogger::init(
-      log_level,
-      ConfigBuilder::new()
-        .set_level_padding(LevelPadding::Right)
-        .set_time_format_str("%FT%T")
-        .build(),
-      log_file,
-    );
+  pub fn new(
+    budget: Budget,
+    concurrency: usize,
+    backend: Backend,
+    profile: Profile,
+    limits: Limits,
+    ignore_robots: bool,
+  ) -> Result<Self, Error> {
+    let archive =
+      Archive::open().map_err(|e| Error::from(format!("Couldn't open page archive:{}", e)))?;
+    let client = profile.client()?;
+    let cookies = if profile.cookies {
+      Some(CookieJar::load()?)
+    } else {
+      None
+    };
+    let quarantine =
+      Quarantine::open().map_err(|e| Error::from(format!("Couldn't open quarantine:{}", e)))?;
+    let limiter = RateLimiter::new(budget);
+    let registry = Registry::new();
+    let robots = if ignore_robots {
+      warn!("Ignoring robots.txt, as asked");
+      None
+    } else {
+      Some(Mutex::new(HashMap::new()))
+    };
+    let concurrency = concurrency.max(1);
 
-    Self { client, throttler }
+    Ok(Self {
+      archive: Arc::new(archive),
+      backend,
+      client,
+      cookies,
+      profile,
+      quarantine,
+      limiter,
+      registry,
+      robots,
+      concurrency,
+      meter: Meter::new(limits),
+    })
   }
 
-  async fn get(&mut self, url: &Url) -> Option<String> {
-    self.throttler.tick();
-    match self
-      .client
-      .get(url.clone())
-      .header(HEADER_KEY, HEADER_VALUE)
-      .send()
-      .await
-    {
-      Ok(response) => match response.text().await {
-        Ok(text) => Some(text),
+  /// Requests `url`, retrying transient failures with jittered exponential backoff
+  ///
+  /// The request is made conditional if the `validators` of a previous fetch are given. A retry of a
+  /// response that came with `Retry-After` waits for the rate limiter to let requests to the host go
+  /// out again.
+  async fn get(&self, url: &Url, page: Page, validators: Option<&Validators>) -> Fetch {
+    with_retries(url, || self.fetch(url, page, validators)).await
+  }
+
+  async fn fetch(&self, url: &Url, page: Page, validators: Option<&Validators>) -> Fetch {
+    if let Backend::Replay(recording) = &self.backend {
+      self.meter.replayed();
+      return match recording.get(url) {
+        Ok(Some((status, text))) => {
+          self.meter.downloaded(text.len());
+          Fetch::from_response(url, status, &HeaderMap::new(), text)
+        }
+        Ok(None) => {
+          warn!("No recorded response to {}", url);
+          Fetch::NotFound(StatusCode::NOT_FOUND)
+        }
+        // A broken recording won't fix itself, so there's no point in retrying
         Err(e) => {
-          error!("Couldn't get text from {}:{}", url, e);
-          None
+          error!("Couldn't replay {}:{}", url, e);
+          Fetch::Rejected(StatusCode::INTERNAL_SERVER_ERROR)
         }
-      },
+      };
+    }
+
+    let user_agent = self.profile.user_agent();
+    if !self.is_allowed(url, user_agent).await {
+      return Fetch::Disallowed;
+    }
+    self.limiter.acquire(url).await;
+    self.meter.requested();
+    let mut request = self.client.get(url.clone()).header(USER_AGENT, user_agent);
+    if let Some((name, value)) = self.cookies.as_ref().and_then(|jar| jar.header(url)) {
+      request = request.header(name, value);
+    }
+    if let Some(validators) = validators {
+      if let Some(etag) = &validators.etag {
+        request = request.header(IF_NONE_MATCH, etag.as_str());
+      }
+      if let Some(last_modified) = &validators.last_modified {
+        request = request.header(IF_MODIFIED_SINCE, last_modified.as_str());
+      }
+    }
+    let response = match within(self.profile.read_timeout(), request.send()).await {
+      Some(Ok(response)) => response,
+      Some(Err(e)) => return Fetch::Transient(format!("Couldn't get response:{}", e)),
+      None => return Fetch::Transient(String::from("Timed out waiting for response")),
+    };
+
+    let status = response.status();
+    let final_url = response.url().clone();
+    if status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::SERVICE_UNAVAILABLE {
+      if let Some(delay) = parse_retry_after(response.headers()) {
+        self.limiter.back_off(url, delay).await;
+      }
+    } else if status.is_success() || status == StatusCode::NOT_MODIFIED {
+      self.limiter.recover(url).await;
+    }
+
+    let headers = response.headers().clone();
+    if let Some(jar) = &self.cookies {
+      if let Err(e) = jar.update(url, &headers) {
+        error!("Couldn't save cookies from {}:{}", url, e);
+      }
+    }
+    let text = match within(self.profile.read_timeout(), response.text()).await {
+      Some(Ok(text)) => text,
+      Some(Err(e)) => return Fetch::Transient(format!("Couldn't get text:{}", e)),
+      None => return Fetch::Transient(String::from("Timed out reading body")),
+    };
+    self.meter.downloaded(text.len());
+
+    self.store(url, page, status, &headers, &text).await;
+    if let Backend::Record(recording) = &self.backend {
+      if let Err(e) = recording.put(url, status, &text) {
+        error!("Couldn't record {}:{}", url, e);
+      }
+    }
+
+    if page == Page::Listing && status.is_success() && !self.is_listing_url(&final_url) {
+      return Fetch::Redirected(final_url);
+    }
+    Fetch::from_response(url, status, &headers, text)
+  }
+
+  /// Archives a fetched page, gzipping and writing it on a blocking thread
+  async fn store(
+    &self,
+    url: &Url,
+    page: Page,
+    status: StatusCode,
+    headers: &HeaderMap,
+    text: &str,
+  ) {
+    let archive = self.archive.clone();
+    let (url, headers, text) = (url.clone(), headers.clone(), text.to_string());
+    match task::spawn_blocking(move || {
+      archive
+        .store(&url, page, status, &headers, &text)
+        .map_err(|e| format!("Couldn't archive {}:{}", url, e))
+    })
+    .await
+    {
+      Ok(Ok(_)) => (),
+      Ok(Err(e)) => error!("{}", e),
+      Err(e) => error!("Couldn't archive page:{}", e),
+    }
+  }
+
+  /// Checks `url` against the `robots.txt` of its site, fetching it the first time around
+  async fn is_allowed(&self, url: &Url, user_agent: &str) -> bool {
+    self
+      .with_robots(url, user_agent, |robots| robots.is_allowed(user_agent, url))
+      .await
+      .unwrap_or(true)
+  }
+
+  /// Looks into the `robots.txt` of `url`'s site, unless it's ignored
+  ///
+  /// A site whose `robots.txt` couldn't be fetched is taken to disallow everything, but only until
+  /// the next lookup, which tries to fetch it again.
+  async fn with_robots<T>(
+    &self,
+    url: &Url,
+    user_agent: &str,
+    f: impl FnOnce(&Robots) -> T,
+  ) -> Option<T> {
+    let (robots, root) = match (&self.robots, &self.backend, Website::from_url(url)) {
+      (_, Backend::Replay(_), _) => return None,
+      (Some(robots), _, Some(website)) => (robots, website.get_root()),
+      _ => return None,
+    };
+    let site = robots.lock().await.entry(root.clone()).or_default().clone();
+    let mut site = site.lock().await;
+    if site.is_none() {
+      match self.fetch_robots(&root, user_agent).await {
+        Some(site_robots) => {
+          if let Some(delay) = site_robots.crawl_delay(user_agent) {
+            self.limiter.slow_down(&root, delay).await;
+          }
+          *site = Some(site_robots);
+        }
+        None => return Some(f(&Robots::disallow_all())),
+      }
+    }
+    site.as_ref().map(f)
+  }
+
+  /// Fetches the `robots.txt` of a site, retrying transient failures. It allows everything if
+  /// there's none, i.e. on a 4xx, and `None` is returned if the site can't be reached or only
+  /// answers with server errors.
+  async fn fetch_robots(&self, root: &Url, user_agent: &str) -> Option<Robots> {
+    let url = match root.join("/robots.txt") {
+      Ok(url) => url,
       Err(e) => {
-        error!("Couldn't get response from {}:{}", url, e);
+        error!("Couldn't build robots.txt URL of {}:{}", root, e);
+        return None;
+      }
+    };
+    match with_retries(&url, || self.request_robots(&url, user_agent)).await {
+      Fetch::Ok(text, _) => {
+        info!("Got {}", url);
+        Some(Robots::parse(&text))
+      }
+      Fetch::Transient(reason) => {
+        error!(
+          "Couldn't get {} ({}), so nothing will be fetched from {} until it can be",
+          url, reason, root
+        );
         None
       }
+      fetch => {
+        info!("{} has no robots.txt ({})", root, fetch);
+        Some(Robots::allow_all())
+      }
+    }
+  }
+
+  /// Requests a `robots.txt`, which is neither archived nor recorded
+  async fn request_robots(&self, url: &Url, user_agent: &str) -> Fetch {
+    self.limiter.acquire(url).await;
+    self.meter.requested();
+    let request = self.client.get(url.clone()).header(USER_AGENT, user_agent);
+    let response = match within(self.profile.read_timeout(), request.send()).await {
+      Some(Ok(response)) => response,
+      Some(Err(e)) => return Fetch::Transient(format!("Couldn't get response:{}", e)),
+      None => return Fetch::Transient(String::from("Timed out waiting for response")),
+    };
+    let status = response.status();
+    if !status.is_success() {
+      if let Some(delay) = parse_retry_after(response.headers()) {
+        self.limiter.back_off(url, delay).await;
+      }
+      return Fetch::from_status(status);
+    }
+    self.limiter.recover(url).await;
+    let headers = response.headers().clone();
+    match within(self.profile.read_timeout(), response.text()).await {
+      Some(Ok(text)) => {
+        self.meter.downloaded(text.len());
+        Fetch::from_response(url, status, &headers, text)
+      }
+      Some(Err(e)) => Fetch::Transient(format!("Couldn't get text:{}", e)),
+      None => Fetch::Transient(String::from("Timed out reading body")),
     }
   }
 
-  pub async fn get_result_urls(
-    &mut self,
-    site: Website,
+  /// The number of pages requested and bytes downloaded so far
+  pub fn traffic(&self) -> (usize, u64) {
+    self.meter.traffic()
+  }
+
+  /// The number of responses served from a recording so far, which aren't counted as requests
+  pub fn replays(&self) -> usize {
+    self.meter.replays()
+  }
+
+  /// Why the crawl stopped early, if it reached one of its limits
+  pub fn stopped(&self) -> Option<String> {
+    self.meter.stopped()
+  }
+
+  fn is_listing_url(&self, url: &Url) -> bool {
+    self
+      .registry
+      .get_for_url(url)
+      .map(|scraper| scraper.is_listing_url(url))
+      .unwrap_or(false)
+  }
+
+  /// The result pages to start crawling a site from, for a search by area and kind
+  pub fn search_roots(
+    &self,
+    site: &Website,
     area: Option<Area>,
     kind: Option<Kind>,
-  ) -> Vec<Url> {
+  ) -> Result<Vec<Url>, Error> {
+    Ok(self.registry.get(site)?.search_roots(area, kind))
+  }
+
+  /// Walks the result pages of search roots, yielding every step of every walk as it arrives along
+  /// with the root it belongs to
+  ///
+  /// The first page of a search root tells of its numbered pages, which are fetched a batch at a
+  /// time. Past them, or without them, the walk follows the links to the next page one at a time.
+  /// Search roots are walked concurrently, and the batches are sized so that the walks together stay
+  /// within the engine's concurrency.
+  ///
+  /// A walk ends at an empty, repeated or missing page, or at the first page whose listings are all
+  /// `known`, which it tells with a `Finished` step. A walk that can't go on because its first page
+  /// or the page to follow a link from couldn't be fetched, or because the crawl reached one of its
+  /// limits, ends without one. Every root comes with the progress of an earlier walk, whose pages
+  /// aren't fetched again.
+  pub fn walk_result_pages<'a>(
+    &'a self,
+    site: &Website,
+    roots: Vec<(Url, WalkProgress)>,
+    known: &'a dyn Fn(&Url) -> bool,
+  ) -> impl Stream<Item = (Url, WalkStep)> + 'a {
+    let (scraper, roots) = match self.registry.get(site) {
+      Ok(scraper) => (Some(scraper), roots),
+      Err(e) => {
+        error!("Couldn't walk result pages:{}", e);
+        (None, vec![])
+      }
+    };
+    let batch = (self.concurrency / roots.len().clamp(1, self.concurrency)).max(1);
+    stream::iter(roots)
+      .map(move |(root, progress)| {
+        let scraper = scraper.expect("INTERNAL ERROR: Search root without a scraper");
+        Box::pin(
+          stream::unfold(Walk::new(root, batch, progress), move |walk| {
+            self.walk_step(walk, scraper, known)
+          })
+          .flat_map(stream::iter),
+        )
+      })
+      .flatten_unordered(self.concurrency)
+  }
+
+  async fn walk_step(
+    &self,
+    mut walk: Walk,
+    scraper: &dyn SiteScraper,
+    known: &dyn Fn(&Url) -> bool,
+  ) -> Option<(Vec<(Url, WalkStep)>, Walk)> {
+    if walk.done {
+      return None;
+    }
+    let first = walk.visited.is_empty();
     let mut result_urls = vec![];
-    let search_roots = urls::get_search_roots(Some(site.clone()), area, kind);
-    for search_url in search_roots.into_iter() {
-      let html = match self.get(&search_url).await {
-        Some(content) => Html::parse_document(&content),
-        None => continue,
+    if first {
+      result_urls.push(walk.root.clone());
+    }
+    while result_urls.len() < walk.batch {
+      match walk.pages.pop_front() {
+        Some(url) if !walk.visited.contains(&url) => result_urls.push(url),
+        Some(_) => (),
+        None => break,
+      }
+    }
+    if result_urls.is_empty() {
+      result_urls.extend(walk.next.take().filter(|url| !walk.visited.contains(url)));
+    }
+    // Out of pages, which is the end of the walk unless it had to give up on the last one
+    if result_urls.is_empty() {
+      walk.done = true;
+      return if walk.failed {
+        None
+      } else {
+        Some((vec![(walk.root.clone(), WalkStep::Finished)], walk))
       };
-      match site {
-        Website::Bazaraki => {
-          let sel =
-            Selector::parse("a.page-number.js-page-filter").expect("Couldn't parse selector");
-          match html
-            .select(&sel)
-            .filter_map(|a| a.inner_html().parse::<u32>().ok())
-            .max()
-          {
-            Some(n_pages) => {
-              for i in 1..=n_pages {
-                result_urls.push(
-                  Url::parse(&format!("{}&page={}", search_url, i))
-                    .expect("Couldn't construct URL"),
-                );
-              }
-            }
-            None => error!("Couldn't get number of result pages from {}\n", search_url),
+    }
+
+    let n_urls = result_urls.len();
+    result_urls.retain(|_| self.meter.admit(Page::Results));
+    let stopped = result_urls.len() < n_urls;
+    walk.visited.extend(result_urls.iter().cloned());
+    let fetches = future::join_all(
+      result_urls
+        .iter()
+        .map(|result_url| self.get(result_url, Page::Results, None)),
+    )
+    .await;
+
+    // Pages are taken in order, so that anything past the last page is left out
+    let mut steps = vec![];
+    let mut last_page = false;
+    for (result_url, fetch) in result_urls.into_iter().zip(fetches) {
+      let content = match fetch {
+        Fetch::Ok(content, _) => content,
+        // The search root itself can't be missing, and may only seem so, e.g. when replayed
+        Fetch::NotFound(status) if !first => {
+          info!("{} is past the last result page ({})", result_url, status);
+          steps.push((walk.root.clone(), WalkStep::Page(result_url, Some(vec![]))));
+          last_page = true;
+          break;
+        }
+        fetch => {
+          error!("Couldn't get {}:{}", result_url, fetch);
+          walk.failed = true;
+          steps.push((walk.root.clone(), WalkStep::Page(result_url, None)));
+          continue;
+        }
+      };
+      walk.failed = false;
+      let html = Html::parse_document(&content);
+
+      if first {
+        match scraper.result_pages(&result_url, &html) {
+          Ok(pages) => {
+            walk.pages = pages.into_iter().skip(1).collect();
+            let pages = walk.pages.iter().cloned().collect();
+            steps.push((walk.root.clone(), WalkStep::Numbered(pages)));
           }
+          Err(e) => warn!("Couldn't get result pages from {}:{}", result_url, e),
         }
-        _ => unimplemented!(),
+      }
+      walk.next = scraper.next_page(&html);
+      if let Some(next) = &walk.next {
+        steps.push((walk.root.clone(), WalkStep::Next(next.clone())));
+      }
+
+      let listing_urls = scraper.listing_links(&html);
+      let mut sorted = listing_urls.clone();
+      sorted.sort();
+      if listing_urls.is_empty() {
+        info!(
+          "{} has no listings, so it's taken for the last result page",
+          result_url
+        );
+        last_page = true;
+      } else if !walk.seen.insert(sorted) {
+        info!("{} repeats an earlier result page", result_url);
+        last_page = true;
+      } else if listing_urls.iter().all(known) {
+        last_page = true;
+      }
+      steps.push((
+        walk.root.clone(),
+        WalkStep::Page(result_url, Some(listing_urls)),
+      ));
+      if last_page {
+        break;
       }
     }
 
-    result_urls
+    if last_page {
+      walk.done = true;
+      steps.push((walk.root.clone(), WalkStep::Finished));
+    } else if stopped {
+      walk.done = true;
+    }
+    Some((steps, walk))
   }
 
-  pub async fn get_listing_urls(&mut self, result_url: Url, site: Website) -> Vec<Url> {
-    let mut listing_urls = vec![];
-    let html = match self.get(&result_url).await {
-      Some(content) => Html::parse_document(&content),
-      None => return listing_urls,
+  /// The sitemaps of a site, as given in its `robots.txt` or else at `/sitemap.xml`
+  pub async fn sitemap_roots(&self, site: &Website) -> Vec<Url> {
+    let root = site.get_root();
+    let mut sitemap_urls = self
+      .with_robots(&root, self.profile.user_agent(), Robots::sitemaps)
+      .await
+      .unwrap_or_default();
+    if sitemap_urls.is_empty() {
+      sitemap_urls.extend(root.join("/sitemap.xml").ok());
+    }
+    sitemap_urls
+  }
+
+  /// Reads sitemap files concurrently, returning what each one lists, with only the site's listings
+  /// kept from the URLs, or `None` if it couldn't be read
+  pub async fn read_sitemaps(&self, site: &Website, urls: Vec<Url>) -> Vec<(Url, Option<Sitemap>)> {
+    let scraper = match self.registry.get(site) {
+      Ok(scraper) => scraper,
+      Err(e) => {
+        error!("Couldn't read sitemaps:{}", e);
+        return urls.into_iter().map(|url| (url, None)).collect();
+      }
     };
-    let root_url = site.get_root();
-    match site {
-      Website::Bazaraki => {
-        let sel = Selector::parse("a.announcement-block__title").unwrap();
-        for selection in html.select(&sel) {
-          let url_str = selection
-            .value()
-            .attr("href")
-            .expect("No 'href' found <a> element");
-          match root_url.join(url_str) {
-            Ok(url) => listing_urls.push(url),
-            Err(e) => error!("Couldn't parse {} as URL:{}\n", url_str, e),
+    stream::iter(urls)
+      .map(|url| async move {
+        let sitemap = match self.get_sitemap(&url).await {
+          Some(Sitemap::UrlSet(urls)) => {
+            let listing_urls: Vec<(Url, Option<DateTime<Utc>>)> = urls
+              .into_iter()
+              .filter(|(url, _)| {
+                Website::from_url(url).as_ref() == Some(site) && scraper.is_listing_url(url)
+              })
+              .collect();
+            info!("Found {} listings in {}", listing_urls.len(), url);
+            Some(Sitemap::UrlSet(listing_urls))
           }
+          sitemap => sitemap,
+        };
+        (url, sitemap)
+      })
+      .buffer_unordered(self.concurrency)
+      .collect()
+      .await
+  }
+
+  /// Fetches and parses a single sitemap file
+  async fn get_sitemap(&self, url: &Url) -> Option<Sitemap> {
+    if !self.meter.admit(Page::Results) {
+      return None;
+    }
+    let content = match &self.backend {
+      Backend::Replay(recording) => match recording.get(url) {
+        Ok(Some((status, text))) if status.is_success() => {
+          self.meter.replayed();
+          self.meter.downloaded(text.len());
+          Ok(text)
         }
+        Ok(_) => Err(Error::from("No recorded response")),
+        Err(e) => Err(e),
+      },
+      _ => self.fetch_sitemap(url).await,
+    };
+    let content = match content {
+      Ok(content) => content,
+      Err(e) => {
+        error!("Couldn't get sitemap {}:{}", url, e);
+        return None;
+      }
+    };
+    if let Backend::Record(recording) = &self.backend {
+      if let Err(e) = recording.put(url, StatusCode::OK, &content) {
+        error!("Couldn't record {}:{}", url, e);
       }
-      _ => unimplemented!(),
     }
+    match Sitemap::parse(&content) {
+      Ok(sitemap) => Some(sitemap),
+      Err(e) => {
+        error!("Couldn't parse sitemap {}:{}", url, e);
+        None
+      }
+    }
+  }
+
+  /// Requests a sitemap file, which may be gzipped, so it's read as bytes rather than as text
+  async fn fetch_sitemap(&self, url: &Url) -> Result<String, Error> {
+    let user_agent = self.profile.user_agent();
+    if !self.is_allowed(url, user_agent).await {
+      return Err(Error::from("Disallowed by robots.txt"));
+    }
+    self.limiter.acquire(url).await;
+    self.meter.requested();
+    let request = self.client.get(url.clone()).header(USER_AGENT, user_agent);
+    let response = match within(self.profile.read_timeout(), request.send()).await {
+      Some(response) => response.map_err(Error::from)?,
+      None => return Err(Error::from("Timed out waiting for response")),
+    };
+    if !response.status().is_success() {
+      return Err(Error::from(format!("Got {}", response.status())));
+    }
+    let body = match within(self.profile.read_timeout(), response.bytes()).await {
+      Some(body) => body.map_err(Error::from)?,
+      None => return Err(Error::from("Timed out reading body")),
+    };
+    self.meter.downloaded(body.len());
+    sitemap::decode(&body)
+  }
 
-    listing_urls
+  /// Fetches result pages concurrently, yielding the listing URLs found in each one as it arrives, or
+  /// `None` if it couldn't be fetched
+  pub fn get_all_listing_urls(
+    &self,
+    result_urls: Vec<Url>,
+  ) -> impl Stream<Item = (Url, Option<Vec<Url>>)> + '_ {
+    stream::iter(result_urls)
+      .map(move |result_url| async move {
+        let listing_urls = self.get_listing_urls(&result_url).await;
+        (result_url, listing_urls)
+      })
+      .buffer_unordered(self.concurrency)
   }
 
-  pub async fn get_listing(&mut self, url: &Url, website: &Website) -> Option<Listing> {
-    self
-      .get(url)
-      .await
-      .map(
-        |c| match Listing::try_from_html(&Html::parse_document(&c), url, website) {
-          Ok(listing) => Some(listing),
+  pub async fn get_listing_urls(&self, result_url: &Url) -> Option<Vec<Url>> {
+    let scraper = match self.registry.get_for_url(result_url) {
+      Ok(scraper) => scraper,
+      Err(e) => {
+        error!("Couldn't get listing URLs from {}:{}", result_url, e);
+        return None;
+      }
+    };
+    if !self.meter.admit(Page::Results) {
+      return None;
+    }
+    match self.get(result_url, Page::Results, None).await {
+      Fetch::Ok(content, _) => Some(scraper.listing_links(&Html::parse_document(&content))),
+      // A missing root still keeps its walk pending, so this can only ever be past the last page
+      Fetch::NotFound(status) => {
+        info!("{} is past the last result page ({})", result_url, status);
+        Some(vec![])
+      }
+      fetch => {
+        error!("Couldn't get {}:{}", result_url, fetch);
+        None
+      }
+    }
+  }
+
+  /// Fetches and parses listings concurrently, yielding each outcome as it arrives
+  ///
+  /// Listings that come with the validators of their previous fetch are revalidated, and only parsed
+  /// if they changed.
+  pub fn get_listings(
+    &self,
+    listings: Vec<(Url, Option<Validators>)>,
+  ) -> impl Stream<Item = (Url, ListingOutcome)> + '_ {
+    stream::iter(listings)
+      .map(move |(url, validators)| async move {
+        let outcome = self.get_listing(&url, validators).await;
+        (url, outcome)
+      })
+      .buffer_unordered(self.concurrency)
+  }
+
+  pub async fn get_listing(&self, url: &Url, validators: Option<Validators>) -> ListingOutcome {
+    let scraper = match self.registry.get_for_url(url) {
+      Ok(scraper) => scraper,
+      Err(e) => {
+        error!("Couldn't get {}:{}", url, e);
+        return ListingOutcome::Failed;
+      }
+    };
+    if !self.meter.admit(Page::Listing) {
+      return ListingOutcome::Skipped;
+    }
+    match self.get(url, Page::Listing, validators.as_ref()).await {
+      Fetch::Ok(content, mut new_validators) => {
+        let html = Html::parse_document(&content);
+        match scraper.parse_listing(&html, url, Utc::now()) {
+          Ok(mut listing) => {
+            listing.set_status(scraper.listing_status(&html));
+            // Servers that ignore conditional requests may still serve the same listing, in a page
+            // with a different view count or other markup that changes on every request
+            new_validators.hash = listing.content_hash();
+            match validators {
+              Some(old) if old.hash == new_validators.hash => {
+                ListingOutcome::Unchanged(new_validators)
+              }
+              _ => ListingOutcome::Parsed(Box::new(listing), new_validators),
+            }
+          }
           Err(err) => {
             error!("Couldn't parse {} : {}", url, err);
-            None
+            if let Err(e) = self.quarantine.put(url, &content, &err) {
+              error!("Couldn't quarantine {}:{}", url, e);
+            }
+            ListingOutcome::Unparsable(err)
           }
-        },
-      )
-      .flatten()
+        }
+      }
+      Fetch::NotModified => match validators {
+        Some(validators) => ListingOutcome::Unchanged(validators),
+        None => {
+          error!("Got an unsolicited 304 for {}", url);
+          ListingOutcome::Failed
+        }
+      },
+      Fetch::NotFound(status) => {
+        info!("{} is gone ({})", url, status);
+        ListingOutcome::Gone
+      }
+      Fetch::Redirected(target) => {
+        info!("{} is gone (redirected to {})", url, target);
+        ListingOutcome::Gone
+      }
+      Fetch::Disallowed => {
+        warn!("Skipping {}, as robots.txt disallows it", url);
+        ListingOutcome::Skipped
+      }
+      fetch => {
+        error!("Couldn't get {}:{}", url, fetch);
+        ListingOutcome::Failed
+      }
+    }
+  }
+
+  /// Downloads images concurrently, yielding the body of each one as it arrives, or `None` if it
+  /// couldn't be downloaded
+  pub fn get_images(&self, urls: Vec<Url>) -> impl Stream<Item = (Url, Option<Vec<u8>>)> + '_ {
+    stream::iter(urls)
+      .map(move |url| async move {
+        if !self.meter.admit_image() {
+          return (url, None);
+        }
+        match self.get_image(&url).await {
+          Ok(body) => (url, Some(body)),
+          Err(e) => {
+            error!("Couldn't get image {}:{}", url, e);
+            (url, None)
+          }
+        }
+      })
+      .buffer_unordered(self.concurrency)
+  }
+
+  /// Requests an image, which is neither archived nor recorded
+  async fn get_image(&self, url: &Url) -> Result<Vec<u8>, Error> {
+    if let Backend::Replay(_) = &self.backend {
+      return Err(Error::from("Images aren't replayed"));
+    }
+    let user_agent = self.profile.user_agent();
+    if !self.is_allowed(url, user_agent).await {
+      return Err(Error::from("Disallowed by robots.txt"));
+    }
+    self.limiter.acquire(url).await;
+    self.meter.requested();
+    let request = self.client.get(url.clone()).header(USER_AGENT, user_agent);
+    let response = match within(self.profile.read_timeout(), request.send()).await {
+      Some(response) => response.map_err(Error::from)?,
+      None => return Err(Error::from("Timed out waiting for response")),
+    };
+    if !response.status().is_success() {
+      return Err(Error::from(format!("Got {}", response.status())));
+    }
+    let body = match within(self.profile.read_timeout(), response.bytes()).await {
+      Some(body) => body.map_err(Error::from)?,
+      None => return Err(Error::from("Timed out reading body")),
+    };
+    self.meter.downloaded(body.len());
+    Ok(body.to_vec())
+  }
+}
+
+/// How far the walk through the result pages of a search root got
+struct Walk {
+  root: Url,
+  /// How many numbered pages are fetched at once
+  batch: usize,
+  /// Numbered result pages yet to walk
+  pages: VecDeque<Url>,
+  /// The link to the next page off of the latest page, to follow once out of numbered pages
+  next: Option<Url>,
+  visited: HashSet<Url>,
+  /// The listing URLs of every page walked, sorted
+  seen: HashSet<Vec<Url>>,
+  /// Whether the latest page couldn't be fetched, so there may be more pages the walk can't tell of
+  failed: bool,
+  done: bool,
+}
+
+impl Walk {
+  fn new(root: Url, batch: usize, progress: WalkProgress) -> Self {
+    let mut visited = HashSet::new();
+    if progress.started {
+      visited.insert(root.clone());
+    }
+    Self {
+      root,
+      batch,
+      pages: progress.pages.into(),
+      next: progress.next,
+      visited,
+      seen: HashSet::new(),
+      failed: false,
+      done: false,
+    }
+  }
+}
+
+/// Makes a request until it doesn't fail transiently, or it was attempted `MAX_ATTEMPTS` times,
+/// with jittered exponential backoff in between
+async fn with_retries<F: Future<Output = Fetch>>(url: &Url, request: impl Fn() -> F) -> Fetch {
+  let mut attempt = 0;
+  loop {
+    let fetch = request().await;
+    match fetch {
+      Fetch::Transient(ref reason) if attempt + 1 < MAX_ATTEMPTS => {
+        let delay = backoff(attempt);
+        warn!(
+          "Attempt #{} at {} failed ({}), retrying in {:?}",
+          attempt + 1,
+          url,
+          reason,
+          delay
+        );
+        delay_for(delay).await;
+        attempt += 1;
+      }
+      _ => return fetch,
+    }
+  }
+}
+
+/// Picks a random delay in the upper half of an exponentially growing window
+fn backoff(attempt: u32) -> Duration {
+  let window = BACKOFF_BASE * 2_u32.pow(attempt);
+  let half = window.as_millis() as u64 / 2;
+  Duration::from_millis(half + rand::thread_rng().gen_range(0, half + 1))
+}
+
+/// Awaits `future`, giving up on it after `limit` if there is one
+async fn within<T>(limit: Option<Duration>, future: impl Future<Output = T>) -> Option<T> {
+  match limit {
+    Some(limit) => timeout(limit, future).await.ok(),
+    None => Some(future.await),
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::checkpoint::Checkpoint;
+  use crate::io::set_data_dir;
+  use std::env::temp_dir;
+  use std::fs::read_to_string;
+  use std::path::Path;
+  use std::str::FromStr;
+
+  /// An engine that replays the fixtures, keeping its data in a scratch directory
+  fn replay_engine(limits: Limits) -> Engine {
+    let _ = set_data_dir(temp_dir().join(format!("truffles-test-{}", std::process::id())));
+    Engine::new(
+      Budget::new(None, None),
+      DEFAULT_CONCURRENCY,
+      Backend::Replay(Recording::open(Path::new("res")).unwrap()),
+      Profile::default(),
+      limits,
+      false,
+    )
+    .unwrap()
+  }
+
+  /// Walks a search root from where an earlier walk got, returning every step
+  async fn walk_from(engine: &Engine, root: &Url, progress: WalkProgress) -> Vec<WalkStep> {
+    let known = |_: &Url| false;
+    engine
+      .walk_result_pages(&Website::Bazaraki, vec![(root.clone(), progress)], &known)
+      .map(|(_, step)| step)
+      .collect()
+      .await
+  }
+
+  /// Walks a search root, summing up every page as the number of listings on it, or `None` if it
+  /// couldn't be fetched, and the end of the walk as `Finished`
+  async fn walk(engine: &Engine, root: &str) -> Vec<String> {
+    let root = Url::from_str(root).unwrap();
+    summarise(&walk_from(engine, &root, WalkProgress::default()).await)
+  }
+
+  fn summarise(steps: &[WalkStep]) -> Vec<String> {
+    steps
+      .iter()
+      .filter_map(|step| match step {
+        WalkStep::Page(_, Some(urls)) => Some(urls.len().to_string()),
+        WalkStep::Page(_, None) => Some(String::from("None")),
+        WalkStep::Finished => Some(String::from("Finished")),
+        _ => None,
+      })
+      .collect()
+  }
+
+  #[tokio::test]
+  async fn engine_walk() {
+    let root = "https://www.bazaraki.com/real-estate/houses-and-villas-sale/lemesos-district-limassol/?ordering=newest";
+    let engine = replay_engine(Limits::default());
+    let html = Html::parse_document(&read_to_string("res/results_1.html").unwrap());
+    let n = engine
+      .registry
+      .get(&Website::Bazaraki)
+      .unwrap()
+      .listing_links(&html)
+      .len();
+    // Numbered pages that weren't recorded come back as 404s, i.e. past the last page
+    assert_eq!(
+      walk(&engine, root).await,
+      [n.to_string(), 0.to_string(), "Finished".to_string()]
+    );
+    // Replayed responses aren't requests
+    assert_eq!(engine.traffic().0, 0);
+    assert!(engine.replays() >= 2);
+
+    // A missing search root is a failure rather than an empty search
+    let missing = "https://www.bazaraki.com/real-estate/plots-of-land/?ordering=newest";
+    assert_eq!(walk(&engine, missing).await, ["None"]);
+
+    // A walk that reaches a limit doesn't finish
+    let engine = replay_engine(Limits {
+      result_pages: Some(1),
+      ..Limits::default()
+    });
+    assert_eq!(walk(&engine, root).await, [n.to_string()]);
+    assert!(engine.stopped().is_some());
+  }
+
+  #[tokio::test]
+  async fn engine_walk_resume() {
+    let root = Url::from_str("https://www.bazaraki.com/real-estate/houses-and-villas-sale/lemesos-district-limassol/?ordering=newest").unwrap();
+    let limits = || Limits {
+      result_pages: Some(1),
+      ..Limits::default()
+    };
+    let mut checkpoint = Checkpoint::start().unwrap();
+    checkpoint.set_roots(std::slice::from_ref(&root)).unwrap();
+
+    // The crawl stops after the first page
+    let engine = replay_engine(limits());
+    let steps = walk_from(&engine, &root, WalkProgress::default()).await;
+    for step in steps.iter() {
+      checkpoint.walk_step(&root, step).unwrap();
+    }
+    assert!(engine.stopped().is_some());
+    assert_eq!(checkpoint.pending_roots(), vec![root.clone()]);
+
+    // The resumed crawl doesn't fetch the first page again, so that it gets further with the same
+    // limit
+    let progress = checkpoint.walk_progress(&root);
+    assert!(progress.started);
+    assert_eq!(progress.pages.len(), 211);
+    let engine = replay_engine(limits());
+    let steps = walk_from(&engine, &root, progress).await;
+    assert!(steps
+      .iter()
+      .all(|step| !matches!(step, WalkStep::Page(url, _) if url == &root)));
+    assert_eq!(summarise(&steps), ["0", "Finished"]);
+    assert_eq!(engine.replays(), 1);
+    for step in steps.iter() {
+      checkpoint.walk_step(&root, step).unwrap();
+    }
+    assert!(checkpoint.is_complete());
+    checkpoint.finish().unwrap();
+  }
+
+  /// An engine that makes live requests, keeping its data in a scratch directory
+  fn live_engine(profile: Profile, ignore_robots: bool) -> Engine {
+    let _ = set_data_dir(temp_dir().join(format!("truffles-test-{}", std::process::id())));
+    Engine::new(
+      Budget::new(None, Some(Duration::from_secs(0))),
+      DEFAULT_CONCURRENCY,
+      Backend::Live,
+      profile,
+      Limits::default(),
+      ignore_robots,
+    )
+    .unwrap()
+  }
+
+  /// Serves a local HTTP server that answers one request after another with `responses`, given as
+  /// status lines and bodies, returning its address
+  async fn serve(responses: Vec<(&'static str, String)>) -> String {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+      for (status, body) in responses {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut request = [0; 4096];
+        let _ = socket.read(&mut request).await.unwrap();
+        let response = format!(
+          "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+          status,
+          body.len(),
+          body
+        );
+        socket.write_all(response.as_bytes()).await.unwrap();
+      }
+    });
+    address
+  }
+
+  #[tokio::test]
+  async fn engine_robots_retry() {
+    // A server that's briefly down before it serves its robots.txt
+    let rules = "User-agent: *\nDisallow: /private\n";
+    let address = serve(vec![
+      ("503 Service Unavailable", String::new()),
+      ("200 OK", String::from(rules)),
+    ])
+    .await;
+    let root = Url::from_str(&format!("http://{}/", address)).unwrap();
+    let engine = live_engine(Profile::default(), false);
+    let robots = engine.fetch_robots(&root, "truffles").await.unwrap();
+    assert!(robots.is_allowed("truffles", &root.join("/listing").unwrap()));
+    assert!(!robots.is_allowed("truffles", &root.join("/private").unwrap()));
+    assert_eq!(engine.traffic().0, 2);
+  }
+
+  #[tokio::test]
+  async fn engine_listing_unchanged() {
+    // The same listing, served again with another view count and form token, and then at another
+    // price
+    let page = read_to_string("res/listing_1.html").unwrap();
+    let token = "idldDkaOPSIT3lb45t8Cm6RwPz4bwpZz1kzP96Yy3sGA88qNksVu3SCT";
+    let volatile = page
+      .replace("Views: 58", "Views: 59")
+      .replace(token, "another-token");
+    let repriced = volatile.replace("content=\"950000.00\"", "content=\"900000.00\"");
+    let address = serve(vec![
+      ("200 OK", page),
+      ("200 OK", volatile),
+      ("200 OK", repriced),
+    ])
+    .await;
+    // Listings are requested through the local server as a proxy, for it to stand in for the site
+    let profile = Profile {
+      proxy: Some(format!("http://{}", address)),
+      ..Profile::default()
+    };
+    let engine = live_engine(profile, true);
+    let url =
+      Url::from_str("http://www.bazaraki.com/adv/2629192_4-bedroom-house-villa-in-ekali/").unwrap();
+
+    let validators = match engine.get_listing(&url, None).await {
+      ListingOutcome::Parsed(_, validators) => validators,
+      outcome => panic!("{:?}", outcome),
+    };
+    let validators = match engine.get_listing(&url, Some(validators)).await {
+      ListingOutcome::Unchanged(validators) => validators,
+      outcome => panic!("{:?}", outcome),
+    };
+    assert!(matches!(
+      engine.get_listing(&url, Some(validators)).await,
+      ListingOutcome::Parsed(..)
+    ));
+  }
+
+  #[test]
+  fn fetch_status() {
+    let classify = |code: u16| Fetch::from_status(StatusCode::from_u16(code).unwrap());
+    assert!(matches!(classify(304), Fetch::NotModified));
+    for code in [404, 410] {
+      assert!(matches!(classify(code), Fetch::NotFound(_)), "{}", code);
+    }
+    for code in [401, 403] {
+      assert!(matches!(classify(code), Fetch::Blocked(_)), "{}", code);
+    }
+    for code in [408, 429, 500, 502, 503, 504] {
+      assert!(matches!(classify(code), Fetch::Transient(_)), "{}", code);
+    }
+    for code in [400, 405, 418, 451] {
+      assert!(matches!(classify(code), Fetch::Rejected(_)), "{}", code);
+    }
+  }
+
+  #[test]
+  fn fetch_backoff() {
+    for attempt in 0..MAX_ATTEMPTS {
+      let window = BACKOFF_BASE * 2_u32.pow(attempt);
+      for _ in 0..100 {
+        let delay = backoff(attempt);
+        assert!(delay >= window / 2 && delay <= window, "{:?}", delay);
+      }
+    }
   }
 }
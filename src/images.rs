@@ -0,0 +1,185 @@
+use chrono::{DateTime, Utc};
+use csv::{Reader, Writer, WriterBuilder};
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView};
+use reqwest::Url;
+use sha2::{Digest, Sha256};
+
+use std::collections::HashMap;
+use std::fs::{create_dir_all, File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+use crate::error::Error;
+use crate::io::{
+  data_path, timestamp_deserializer, timestamp_serializer, url_deserializer, url_serializer,
+};
+
+/// An image of a listing, as kept in `images.csv` next to the cached listings
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ListingImage {
+  /// UID of the listing
+  pub id: String,
+  /// URL of the listing
+  #[serde(
+    serialize_with = "url_serializer",
+    deserialize_with = "url_deserializer"
+  )]
+  pub url: Url,
+  /// Time the listing was read
+  #[serde(
+    serialize_with = "timestamp_serializer",
+    deserialize_with = "timestamp_deserializer"
+  )]
+  pub timestamp: DateTime<Utc>,
+  /// Where the image comes in the listing's gallery, starting at 0
+  pub position: usize,
+  /// URL of the image
+  #[serde(
+    serialize_with = "url_serializer",
+    deserialize_with = "url_deserializer"
+  )]
+  pub image: Url,
+}
+
+/// Index record of a downloaded image
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Image {
+  /// URL the image was downloaded from
+  #[serde(
+    serialize_with = "url_serializer",
+    deserialize_with = "url_deserializer"
+  )]
+  pub url: Url,
+  /// Time of the download
+  #[serde(
+    serialize_with = "timestamp_serializer",
+    deserialize_with = "timestamp_deserializer"
+  )]
+  pub timestamp: DateTime<Utc>,
+  /// SHA-256 of the file, which also names it
+  pub hash: String,
+  /// Size of the file in bytes
+  pub bytes: usize,
+  pub width: u32,
+  pub height: u32,
+  /// Difference hash of the picture, which stays about the same when it's scaled or recompressed
+  pub dhash: String,
+}
+
+/// Downloaded listing images, kept under `~/.truffles/images`
+///
+/// Every image is saved as `<sha256>.<extension>`, so the same picture is only stored once, while
+/// `index.csv` records the URL, size and perceptual hash of every download.
+pub struct ImageStore {
+  dir: PathBuf,
+  images: HashMap<Url, Image>,
+  index: Writer<File>,
+}
+
+impl ImageStore {
+  pub fn open() -> Result<Self, Error> {
+    let dir = data_path("images");
+    create_dir_all(&dir).map_err(Error::from)?;
+
+    let index_path = dir.join("index.csv");
+    let mut images = HashMap::new();
+    if index_path.exists() {
+      for image in Reader::from_path(&index_path)
+        .map_err(Error::from)?
+        .deserialize::<Image>()
+      {
+        let image = image.map_err(Error::from)?;
+        images.insert(image.url.clone(), image);
+      }
+    }
+    let index = WriterBuilder::new()
+      .has_headers(images.is_empty())
+      .from_writer(
+        OpenOptions::new()
+          .create(true)
+          .append(true)
+          .open(index_path)
+          .map_err(Error::from)?,
+      );
+
+    Ok(Self { dir, images, index })
+  }
+
+  /// Whether the image at `url` was already downloaded
+  pub fn contains(&self, url: &Url) -> bool {
+    self.images.contains_key(url)
+  }
+
+  /// Saves a downloaded image along with its metadata
+  pub fn put(&mut self, url: &Url, body: &[u8]) -> Result<&Image, Error> {
+    let format = image::guess_format(body)
+      .map_err(|e| Error::from(format!("Couldn't tell image format:{}", e)))?;
+    let picture = image::load_from_memory_with_format(body, format)
+      .map_err(|e| Error::from(format!("Couldn't decode image:{}", e)))?;
+
+    let hash = hex::encode(Sha256::digest(body));
+    let extension = format.extensions_str().first().unwrap_or(&"img");
+    let path = self.dir.join(format!("{}.{}", hash, extension));
+    if !path.exists() {
+      File::create(&path)
+        .and_then(|mut f| f.write_all(body))
+        .map_err(Error::from)?;
+    }
+
+    let (width, height) = picture.dimensions();
+    let image = Image {
+      url: url.clone(),
+      timestamp: Utc::now(),
+      hash,
+      bytes: body.len(),
+      width,
+      height,
+      dhash: format!("{:016x}", dhash(&picture)),
+    };
+    self.index.serialize(&image).map_err(Error::from)?;
+    self.index.flush().map_err(Error::from)?;
+    Ok(self.images.entry(url.clone()).or_insert(image))
+  }
+}
+
+/// Hashes a picture by whether each pixel of a 9x8 grayscale thumbnail is brighter than the next
+/// one in its row, so that similar pictures differ in few bits
+pub fn dhash(picture: &DynamicImage) -> u64 {
+  let thumbnail = picture.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+  let mut hash = 0;
+  for y in 0..8 {
+    for x in 0..8 {
+      let bit = thumbnail.get_pixel(x, y)[0] > thumbnail.get_pixel(x + 1, y)[0];
+      hash = (hash << 1) | bit as u64;
+    }
+  }
+  hash
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use std::fs::read;
+
+  #[test]
+  fn image_dhash() {
+    let picture = image::load_from_memory(
+      &read("res/listing_1_files/76e3e821087c5eacb7a7ccb7ed7babca.jpg").unwrap(),
+    )
+    .unwrap();
+    let hash = dhash(&picture);
+    let (width, height) = picture.dimensions();
+
+    // Scaling the picture down barely changes its hash
+    let smaller = picture.resize_exact(width / 3, height / 3, FilterType::Triangle);
+    assert!((hash ^ dhash(&smaller)).count_ones() <= 4);
+
+    // Another picture of the same listing is told apart
+    let other = image::load_from_memory(
+      &read("res/listing_1_files/dfac1478bb734fa1d2bf1c155c031223.jpg").unwrap(),
+    )
+    .unwrap();
+    assert!((hash ^ dhash(&other)).count_ones() > 16);
+  }
+}
@@ -1,8 +1,18 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDateTime, Utc};
 use reqwest::Url;
 use serde::{Deserializer, Serializer};
 
+use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::OnceLock;
+
+use crate::error::Error;
+
+/// How times without a timezone are written out
+const LOCAL_TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// Data directory to use instead of `~/.truffles`, if one was set
+static DATA_DIR: OnceLock<PathBuf> = OnceLock::new();
 
 pub fn timestamp_serializer<S>(val: &DateTime<Utc>, s: S) -> Result<S::Ok, S::Error>
 where
@@ -19,6 +29,26 @@ where
   Ok(DateTime::from_str(s).expect("Couldn't parse datetime"))
 }
 
+pub fn local_time_serializer<S>(val: &Option<NaiveDateTime>, s: S) -> Result<S::Ok, S::Error>
+where
+  S: Serializer,
+{
+  match val {
+    Some(val) => s.serialize_str(&val.format(LOCAL_TIME_FORMAT).to_string()),
+    None => s.serialize_none(),
+  }
+}
+
+pub fn local_time_deserializer<'de, D>(d: D) -> Result<Option<NaiveDateTime>, D::Error>
+where
+  D: Deserializer<'de>,
+{
+  let s: Option<String> = serde::de::Deserialize::deserialize(d)?;
+  Ok(s.filter(|s| !s.is_empty()).map(|s| {
+    NaiveDateTime::parse_from_str(&s, LOCAL_TIME_FORMAT).expect("Couldn't parse local time")
+  }))
+}
+
 pub fn url_serializer<S>(val: &Url, s: S) -> Result<S::Ok, S::Error>
 where
   S: Serializer,
@@ -33,3 +63,22 @@ where
   let s: &str = serde::de::Deserialize::deserialize(d)?;
   Ok(Url::from_str(s).expect("Couldn't parse Url"))
 }
+
+/// Resolves `path` relative to the data directory (`~/.truffles`, unless set otherwise)
+pub fn data_path(path: &str) -> PathBuf {
+  match DATA_DIR.get() {
+    Some(dir) => dir.join(path),
+    None => dirs::home_dir()
+      .expect("Couldn't get home directory")
+      .join(".truffles")
+      .join(path),
+  }
+}
+
+/// Points the data directory somewhere other than `~/.truffles` for the rest of the run, which can
+/// only be done once and before anything is read or written
+pub fn set_data_dir(dir: PathBuf) -> Result<(), Error> {
+  DATA_DIR
+    .set(dir)
+    .map_err(|_| Error::from("Data directory was already set"))
+}
@@ -0,0 +1,195 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::archive::Page;
+
+/// Hard limits on how much a single crawl gets to do
+#[derive(Clone, Debug, Default)]
+pub struct Limits {
+  /// Number of result pages fetched
+  pub result_pages: Option<usize>,
+  /// Number of listing pages fetched
+  pub listings: Option<usize>,
+  /// Wall-clock time
+  pub time: Option<Duration>,
+  /// Bytes of page bodies downloaded
+  pub bytes: Option<u64>,
+}
+
+/// Keeps track of what a crawl did, and tells when it has to stop
+pub struct Meter {
+  limits: Limits,
+  started: Instant,
+  result_pages: AtomicUsize,
+  listings: AtomicUsize,
+  requests: AtomicUsize,
+  /// Responses served from a recording rather than requested
+  replays: AtomicUsize,
+  bytes: AtomicU64,
+  /// Why the crawl stopped, once a limit was reached
+  stopped: Mutex<Option<String>>,
+}
+
+impl Meter {
+  pub fn new(limits: Limits) -> Self {
+    Self {
+      limits,
+      started: Instant::now(),
+      result_pages: AtomicUsize::new(0),
+      listings: AtomicUsize::new(0),
+      requests: AtomicUsize::new(0),
+      replays: AtomicUsize::new(0),
+      bytes: AtomicU64::new(0),
+      stopped: Mutex::new(None),
+    }
+  }
+
+  /// Tells whether a page may be fetched without going over any limit, and counts it if so
+  pub fn admit(&self, page: Page) -> bool {
+    let reason = self.over_budget().or_else(|| {
+      let (count, limit, what) = match page {
+        Page::Results => (&self.result_pages, self.limits.result_pages, "result pages"),
+        Page::Listing => (&self.listings, self.limits.listings, "listings"),
+      };
+      let n = count.fetch_add(1, Ordering::Relaxed);
+      match limit {
+        Some(limit) if n >= limit => Some(format!("fetched {} {}", limit, what)),
+        _ => None,
+      }
+    });
+    self.check(reason)
+  }
+
+  /// Tells whether an image may be downloaded without running out of time or bytes
+  pub fn admit_image(&self) -> bool {
+    self.check(self.over_budget())
+  }
+
+  /// Why the crawl can't go on, if it ran out of time or bytes
+  fn over_budget(&self) -> Option<String> {
+    if self
+      .limits
+      .time
+      .map(|time| self.started.elapsed() >= time)
+      .unwrap_or(false)
+    {
+      Some(String::from("ran out of time"))
+    } else if self
+      .limits
+      .bytes
+      .map(|bytes| self.bytes.load(Ordering::Relaxed) >= bytes)
+      .unwrap_or(false)
+    {
+      Some(String::from("downloaded the maximum number of bytes"))
+    } else {
+      None
+    }
+  }
+
+  /// Records the reason the crawl has to stop, if there is one
+  fn check(&self, reason: Option<String>) -> bool {
+    match reason {
+      Some(reason) => {
+        let mut stopped = self
+          .stopped
+          .lock()
+          .expect("INTERNAL ERROR: Meter lock poisoned");
+        if stopped.is_none() {
+          warn!("Stopping the crawl, as it {}", reason);
+          *stopped = Some(reason);
+        }
+        false
+      }
+      None => true,
+    }
+  }
+
+  pub fn requested(&self) {
+    self.requests.fetch_add(1, Ordering::Relaxed);
+  }
+
+  pub fn replayed(&self) {
+    self.replays.fetch_add(1, Ordering::Relaxed);
+  }
+
+  pub fn downloaded(&self, bytes: usize) {
+    self.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+  }
+
+  /// The number of pages requested and bytes downloaded so far
+  pub fn traffic(&self) -> (usize, u64) {
+    (
+      self.requests.load(Ordering::Relaxed),
+      self.bytes.load(Ordering::Relaxed),
+    )
+  }
+
+  /// The number of responses served from a recording so far
+  pub fn replays(&self) -> usize {
+    self.replays.load(Ordering::Relaxed)
+  }
+
+  /// Why the crawl stopped early, if it did
+  pub fn stopped(&self) -> Option<String> {
+    self
+      .stopped
+      .lock()
+      .expect("INTERNAL ERROR: Meter lock poisoned")
+      .clone()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn meter_admit() {
+    let meter = Meter::new(Limits {
+      result_pages: Some(2),
+      listings: Some(1),
+      ..Limits::default()
+    });
+    assert!(meter.admit(Page::Results));
+    assert!(meter.admit(Page::Listing));
+    assert!(meter.admit(Page::Results));
+    assert_eq!(meter.stopped(), None);
+
+    // Pages past a limit are turned away, and the first limit reached is why the crawl stopped
+    assert!(!meter.admit(Page::Results));
+    assert!(!meter.admit(Page::Listing));
+    assert!(!meter.admit(Page::Results));
+    assert_eq!(meter.stopped().as_deref(), Some("fetched 2 result pages"));
+
+    // Images only count against time and bytes
+    assert!(meter.admit_image());
+  }
+
+  #[test]
+  fn meter_admit_image() {
+    let meter = Meter::new(Limits {
+      bytes: Some(1000),
+      ..Limits::default()
+    });
+    meter.requested();
+    meter.downloaded(600);
+    assert!(meter.admit_image());
+    assert!(meter.admit(Page::Listing));
+    meter.downloaded(400);
+    assert!(!meter.admit_image());
+    assert!(!meter.admit(Page::Results));
+    assert_eq!(meter.traffic(), (1, 1000));
+    assert_eq!(
+      meter.stopped().as_deref(),
+      Some("downloaded the maximum number of bytes")
+    );
+
+    let meter = Meter::new(Limits {
+      time: Some(Duration::from_secs(0)),
+      ..Limits::default()
+    });
+    assert!(!meter.admit_image());
+    assert_eq!(meter.stopped().as_deref(), Some("ran out of time"));
+  }
+}
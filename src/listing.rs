@@ -1,18 +1,24 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDateTime, Utc};
 use regex::Regex;
 use reqwest::Url;
 use scraper::Html;
+use sha2::{Digest, Sha256};
 
 use std::str::FromStr;
+use std::time::UNIX_EPOCH;
 
+use crate::area::Area;
 use crate::error::Error;
 use crate::lookup::Lookup;
-use crate::parse;
 use crate::plot::Plot;
+use crate::precision::Precision;
 use crate::property::Property;
+use crate::seller::Seller;
 use crate::site::Website;
+use crate::sites::Registry;
+use crate::status::Status;
 
-#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 #[serde(untagged)]
 pub enum Listing {
   Plot(Plot),
@@ -27,6 +33,13 @@ impl Listing {
     }
   }
 
+  pub fn id(&self) -> &str {
+    match self {
+      Self::Plot(plot) => &plot.id,
+      Self::Property(prop) => &prop.id,
+    }
+  }
+
   pub fn timestamp(&self) -> &DateTime<Utc> {
     match self {
       Self::Plot(plot) => &plot.timestamp,
@@ -34,6 +47,13 @@ impl Listing {
     }
   }
 
+  pub fn set_timestamp(&mut self, timestamp: DateTime<Utc>) {
+    match self {
+      Self::Plot(plot) => plot.timestamp = timestamp,
+      Self::Property(prop) => prop.timestamp = timestamp,
+    }
+  }
+
   pub fn url(&self) -> &Url {
     match self {
       Self::Plot(plot) => &plot.url,
@@ -41,11 +61,197 @@ impl Listing {
     }
   }
 
-  pub fn try_from_html(html: &Html, url: &Url, website: &Website) -> Result<Self, Error> {
-    match website {
-      Website::Bazaraki => parse::parse_bazaraki(html, url),
-      _ => unimplemented!(),
+  pub fn website(&self) -> &Website {
+    match self {
+      Self::Plot(plot) => &plot.website,
+      Self::Property(prop) => &prop.website,
+    }
+  }
+
+  pub fn area(&self) -> &Area {
+    match self {
+      Self::Plot(plot) => &plot.area,
+      Self::Property(prop) => &prop.area,
+    }
+  }
+
+  pub fn status(&self) -> Status {
+    match self {
+      Self::Plot(plot) => plot.status,
+      Self::Property(prop) => prop.status,
+    }
+  }
+
+  pub fn set_status(&mut self, status: Status) {
+    match self {
+      Self::Plot(plot) => plot.status = status,
+      Self::Property(prop) => prop.status = status,
+    }
+  }
+
+  /// Carries the first sighting over from the previous record of the listing, unless it had ended
+  pub fn follow(&mut self, previous: &Listing) {
+    if !previous.status().is_live() {
+      return;
     }
+    let first_seen = match previous {
+      Self::Plot(plot) => plot.first_seen,
+      Self::Property(prop) => prop.first_seen,
+    };
+    self.set_first_seen(first_seen);
+  }
+
+  pub fn set_first_seen(&mut self, timestamp: DateTime<Utc>) {
+    match self {
+      Self::Plot(plot) => plot.first_seen = timestamp,
+      Self::Property(prop) => prop.first_seen = timestamp,
+    }
+  }
+
+  /// Sets what the page tells about the listing itself, apart from the property or plot
+  pub fn set_details(
+    &mut self,
+    posted_at: Option<NaiveDateTime>,
+    views: Option<u32>,
+    title: Option<String>,
+  ) {
+    match self {
+      Self::Plot(plot) => {
+        plot.posted_at = posted_at;
+        plot.views = views;
+        plot.title = title;
+      }
+      Self::Property(prop) => {
+        prop.posted_at = posted_at;
+        prop.views = views;
+        prop.title = title;
+      }
+    }
+  }
+
+  /// Sets where the listing is, as latitude, longitude and how precise they are
+  pub fn set_location(&mut self, location: Option<(f64, f64, Precision)>) {
+    let (lat, lon, precision) = match location {
+      Some((lat, lon, precision)) => (Some(lat), Some(lon), Some(precision)),
+      None => (None, None, None),
+    };
+    match self {
+      Self::Plot(plot) => {
+        plot.lat = lat;
+        plot.lon = lon;
+        plot.precision = precision;
+      }
+      Self::Property(prop) => {
+        prop.lat = lat;
+        prop.lon = lon;
+        prop.precision = precision;
+      }
+    }
+  }
+
+  pub fn seller(&self) -> Option<&Seller> {
+    match self {
+      Self::Plot(plot) => plot.seller.as_ref(),
+      Self::Property(prop) => prop.seller.as_ref(),
+    }
+  }
+
+  pub fn seller_id(&self) -> Option<&str> {
+    match self {
+      Self::Plot(plot) => plot.seller_id.as_deref(),
+      Self::Property(prop) => prop.seller_id.as_deref(),
+    }
+  }
+
+  /// Links the listing to its seller
+  pub fn set_seller(&mut self, seller: Option<Seller>) {
+    let seller_id = seller.as_ref().map(|seller| seller.id.clone());
+    match self {
+      Self::Plot(plot) => {
+        plot.seller_id = seller_id;
+        plot.seller = seller;
+      }
+      Self::Property(prop) => {
+        prop.seller_id = seller_id;
+        prop.seller = seller;
+      }
+    }
+  }
+
+  pub fn description(&self) -> Option<&str> {
+    match self {
+      Self::Plot(plot) => plot.description.as_deref(),
+      Self::Property(prop) => prop.description.as_deref(),
+    }
+  }
+
+  pub fn set_description(&mut self, description: Option<String>) {
+    match self {
+      Self::Plot(plot) => plot.description = description,
+      Self::Property(prop) => prop.description = description,
+    }
+  }
+
+  pub fn images(&self) -> &[Url] {
+    match self {
+      Self::Plot(plot) => &plot.images,
+      Self::Property(prop) => &prop.images,
+    }
+  }
+
+  pub fn set_images(&mut self, images: Vec<Url>) {
+    match self {
+      Self::Plot(plot) => plot.images = images,
+      Self::Property(prop) => prop.images = images,
+    }
+  }
+
+  /// Turns the listing into a record of it being seen live at `timestamp`
+  pub fn seen(&mut self, timestamp: DateTime<Utc>) {
+    match self {
+      Self::Plot(plot) => {
+        plot.timestamp = timestamp;
+        plot.last_seen = timestamp;
+      }
+      Self::Property(prop) => {
+        prop.timestamp = timestamp;
+        prop.last_seen = timestamp;
+      }
+    }
+  }
+
+  /// Turns the listing into a record of it ending with `status` at `timestamp`
+  pub fn end(&mut self, status: Status, timestamp: DateTime<Utc>) {
+    self.set_status(status);
+    self.set_timestamp(timestamp);
+  }
+
+  /// A SHA-256 of what the listing's page tells about it, leaving out what changes from one fetch
+  /// to the next: when it was fetched and how many times it was viewed
+  pub fn content_hash(&self) -> String {
+    let mut listing = self.clone();
+    let epoch = DateTime::<Utc>::from(UNIX_EPOCH);
+    listing.seen(epoch);
+    listing.set_first_seen(epoch);
+    match &mut listing {
+      Self::Plot(plot) => plot.views = None,
+      Self::Property(prop) => prop.views = None,
+    }
+    let fields = serde_json::to_string(&listing).unwrap_or_default();
+    hex::encode(Sha256::digest(fields.as_bytes()))
+  }
+
+  pub fn try_from_html(
+    html: &Html,
+    url: &Url,
+    website: &Website,
+    fetched: DateTime<Utc>,
+  ) -> Result<Self, Error> {
+    let registry = Registry::new();
+    let scraper = registry.get(website)?;
+    let mut listing = scraper.parse_listing(html, url, fetched)?;
+    listing.set_status(scraper.listing_status(html));
+    Ok(listing)
   }
 }
 
@@ -55,7 +261,7 @@ impl Default for Listing {
   }
 }
 
-#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
 pub enum Kind {
   Plot,
   Property,
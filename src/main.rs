@@ -13,34 +13,49 @@ extern crate structopt;
 extern crate serde_derive;
 extern crate tokio;
 
+mod archive;
 mod area;
 mod cache;
+mod checkpoint;
 mod cond;
+mod crawl;
+mod description;
 mod engine;
 mod error;
+mod images;
 mod io;
+mod limits;
 mod listing;
 mod lookup;
 mod parse;
 mod plot;
+mod precision;
+mod profile;
 mod property;
+mod quarantine;
+mod reparse;
+mod replay;
+mod robots;
+mod run;
+mod seller;
 mod site;
+mod sitemap;
+mod sites;
+mod status;
 mod throttle;
 mod urls;
+mod validators;
 
-use chrono::Utc;
-use indicatif::{ProgressBar, ProgressStyle};
 use log::LevelFilter;
+use simplelog::{ConfigBuilder, LevelPadding, WriteLogger};
 use structopt::StructOpt;
 
-use std::collections::HashSet;
-use std::time::Duration;
+use std::fs::{create_dir_all, OpenOptions};
+use std::path::PathBuf;
 
-use crate::area::Area;
-use crate::cache::Cache;
-use crate::engine::Engine;
-use crate::listing::Kind;
-use crate::site::Website;
+use crate::crawl::CrawlArgs;
+use crate::quarantine::QuarantineArgs;
+use crate::reparse::ReparseArgs;
 
 #[derive(Debug, StructOpt)]
 #[structopt(
@@ -48,38 +63,38 @@ use crate::site::Website;
   about = "\ntruffles is a command-line tool that scrapes listings off of real estate websites."
 )]
 struct Args {
-  #[structopt(
-    short = "a",
-    long = "area",
-    help = "Only fetch listings in this area [options: famagusta|larnaka|lefkosia|limassol|paphos]"
-  )]
-  area: Option<Area>,
-  #[structopt(
-    short = "f",
-    long = "force",
-    help = "Fetch all listings regardless of their latest timestamp"
-  )]
-  force: bool,
   #[structopt(
     short = "l",
     long = "level",
     help = "Logging level",
-    default_value = "warn"
+    default_value = "warn",
+    global = true
   )]
   level: LevelFilter,
+
+  #[structopt(flatten)]
+  crawl: CrawlArgs,
+
+  #[structopt(subcommand)]
+  cmd: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+  #[structopt(about = "Scrapes listings off of real estate websites (default)")]
+  Crawl(Box<CrawlArgs>),
   #[structopt(
-    short = "k",
-    long = "kind",
-    help = "Only fetch listings of a specific kind [options: plot|property]"
+    about = "Rebuilds the cache from archived pages without touching the network, or promotes a rebuilt cache"
   )]
-  kind: Option<Kind>,
-
+  Reparse(ReparseArgs),
   #[structopt(
-    short = "t",
-    long = "throttle",
-    help = "An interval (in milliseconds) to wait for between HTTP requests. Defaults to 1000ms"
+    about = "Lists listing pages that couldn't be parsed, or promotes one into a fixture"
   )]
-  throttling: Option<u64>,
+  Quarantine(QuarantineArgs),
+  #[structopt(about = "Lists past crawls and what they did")]
+  Runs,
+  #[structopt(about = "Lists sellers by the number of live listings they have up")]
+  Sellers,
 }
 
 #[tokio::main]
@@ -87,75 +102,44 @@ async fn main() -> Result<(), String> {
   // Parse arguments
   let args: Args = Args::from_args();
 
-  // Initial engine
-  let mut engine = Engine::new(
-    args.level,
-    args.throttling.map(|ms| Duration::from_millis(ms)),
-  );
+  init_logger(args.level);
 
-  // Load cache
-  let mut cache = Cache::load();
+  match args.cmd {
+    None => crawl::crawl(args.crawl).await,
+    Some(Command::Crawl(crawl_args)) => crawl::crawl(*crawl_args).await,
+    Some(Command::Reparse(reparse_args)) => reparse::reparse(reparse_args),
+    Some(Command::Quarantine(quarantine_args)) => quarantine::quarantine(quarantine_args),
+    Some(Command::Runs) => run::runs(),
+    Some(Command::Sellers) => seller::sellers(),
+  }
+}
 
-  // Get result URLs
-  let bar = ProgressBar::new(1);
-  bar.set_style(
-    ProgressStyle::default_bar()
-      .template("{spinner} Snooping result pages ... {percent:>3}% (ETA ~{eta})")
-      .tick_chars("|/-\\-"),
-  );
-  bar.enable_steady_tick(250);
-  let result_urls = engine
-    .get_result_urls(Website::Bazaraki, args.area, args.kind)
-    .await;
-  bar.inc(1);
-  bar.finish();
+fn init_logger(log_level: LevelFilter) {
+  let path = dirs::home_dir()
+    .expect("Couldn't get home directory")
+    .join(PathBuf::from(".truffles/truffles.log"));
 
-  // Get listing URLs
-  let mut listing_urls = HashSet::new();
-  let bar = ProgressBar::new(result_urls.len() as u64);
-  bar.set_style(
-    ProgressStyle::default_bar()
-      .template("{spinner} Getting listings URLs ... {percent:>3}% (ETA ~{eta})")
-      .tick_chars("|/-\\-"),
-  );
-  bar.enable_steady_tick(250);
-  for result_url in result_urls.into_iter() {
-    for url in engine.get_listing_urls(result_url, Website::Bazaraki).await {
-      listing_urls.insert(url);
-    }
-    bar.inc(1);
+  if !path.exists() {
+    create_dir_all(
+      path
+        .parent()
+        .expect("INTERNAL ERROR: Can't get path's parent"),
+    )
+    .expect("INTERNAL ERROR: Can't create directory");
   }
-  bar.finish();
 
-  // Only fetch "stale" listings
-  if !args.force {
-    let now = Utc::now();
-    listing_urls.retain(|url| {
-      cache
-        .get_last_timestamp(url)
-        .map(|timestamp| (now - timestamp).num_days() >= 30)
-        .unwrap_or(true)
-    });
-  }
+  let log_file = OpenOptions::new()
+    .create(true)
+    .append(true)
+    .open(path)
+    .expect(&format!("Couldn't open log file"));
 
-  // Get listing pages, parse them and cache the results
-  let bar = ProgressBar::new(listing_urls.len() as u64);
-  bar.set_style(
-    ProgressStyle::default_bar()
-      .template("{spinner} Getting listings      ... {percent:>3}% (ETA ~{eta})")
-      .tick_chars("|/-\\-"),
+  let _ = WriteLogger::init(
+    log_level,
+    ConfigBuilder::new()
+      .set_level_padding(LevelPadding::Right)
+      .set_time_format_str("%FT%T")
+      .build(),
+    log_file,
   );
-  bar.enable_steady_tick(250);
-  for listing_url in listing_urls.iter() {
-    match engine.get_listing(listing_url, &Website::Bazaraki).await {
-      Some(listing) => {
-        cache.add(listing);
-      }
-      None => continue,
-    }
-    bar.inc(1);
-  }
-  bar.finish();
-
-  Ok(())
 }
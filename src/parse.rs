@@ -1,234 +1,66 @@
-use chrono::Utc;
-use regex::{Regex, RegexBuilder};
+use regex::RegexBuilder;
 use reqwest::Url;
-use scraper::{Html, Selector};
+use scraper::{ElementRef, Html, Selector};
 
-use crate::area::Area;
-use crate::cond::Condition;
 use crate::error::Error;
-use crate::listing::{Kind as ListingKind, Listing};
-use crate::lookup::Lookup;
-use crate::plot::{Kind as PlotKind, Plot};
-use crate::property::{Kind as PropertyKind, Property};
-use crate::site::Website;
 
-use std::iter::Iterator;
-
-pub fn parse_bazaraki(html: &Html, url: &Url) -> Result<Listing, Error> {
-  // Common regular expressions
-  let re_int = Regex::new(r"[0-9]+").expect("INTERNAL ERROR: Couldn't build regex");
-
-  // Common selectors
-  let li_sel = Selector::parse("li").expect("INTERNAL ERROR: Couldn't parse selector");
-  let a_sel = Selector::parse("a").expect("INTERNAL ERROR: Couldn't parse selector");
-  let span_sel = Selector::parse("span").expect("INTERNAL ERROR: Couldn't parse selector");
-
-  // Parse UID
-  let id_sel: Selector =
-    Selector::parse("span[itemprop=\"sku\"").expect("INTERNAL ERROR: Couldn't parse selector");
-  let id: String = format!(
-    "bazaraki_{}",
-    html
-      .select(&id_sel)
-      .next()
-      .ok_or(Error::from("Couldn't select UID element"))?
-      .inner_html()
-  );
-
-  // Get timestamp
-  let timestamp = Utc::now();
-
-  // Parse price
-  let price_sel: Selector =
-    Selector::parse("meta[itemprop=\"price\"]").expect("INTERNAL ERROR: Couldn't parse selector");
-  let price_str: &str = html
-    .select(&price_sel)
-    .next()
-    .ok_or(Error::from("Couldn't select price element"))?
-    .value()
-    .attr("content")
-    .ok_or(Error::from("Couldn't get price's 'content' attribute"))?;
-  let price: u32 = price_str.parse::<f32>().map_err(|e| Error::from(e))? as u32;
-
-  // Parse area
-  let area_sel: Selector =
-    Selector::parse("span[itemprop=\"address\"]").expect("INTERNAL ERROR: Couldn't parse selector");
-  let area_str = html
-    .select(&area_sel)
-    .next()
-    .ok_or(Error::from("Couldn't select area element"))?
-    .inner_html();
-  let area = match Area::lookup(&area_str) {
-    Some(area) => area,
-    None => return Err(Error::from("Couldn't parse area for")),
-  };
-
-  // Get useful html handles
-  let breadcrumbs_sel =
-    Selector::parse("ul.breadcrumbs").expect("INTERNAL ERROR: Couldn't parse selector");
-  let breadcrumbs_html = html
-    .select(&breadcrumbs_sel)
-    .next()
-    .ok_or(Error::from("Couldn't select breadcrumbs"))?
-    .html();
-  let chars_sel = Selector::parse("div.announcement-characteristics")
-    .expect("INTERNAL ERROR: Couldn't parse selector");
-  let chars = html
-    .select(&chars_sel)
-    .next()
-    .ok_or(Error::from("Couldn't select characteristics"))?;
-  let chars_html = chars.inner_html();
-  let desc_sel = Selector::parse("div.announcement-description")
-    .expect("INTERNAL ERROR: Couldn't parse selector");
-  let desc = html
-    .select(&desc_sel)
-    .next()
-    .ok_or(Error::from("Couldn't select description element"))?;
-  let desc_html = desc.inner_html();
-
-  // Parse size
-  let re_size = Regex::new(r"([0-9]+) m²").expect("INTERNAL ERROR: Couldn't parse regex");
-  let size = re_size
-    .captures(&chars_html)
-    .map(|g| g[1].parse::<f32>().map(|a| a as u32).ok())
-    .flatten();
-
-  // Figure out what kind of listing we're parsing
-  let kind = ListingKind::lookup(&breadcrumbs_html)
-    .ok_or(Error::from("Couldn't figure out listing kind"))?;
-
-  match kind {
-    ListingKind::Property => {
-      // Parse property kind
-      let kind = PropertyKind::lookup(&chars_html).ok_or(Error::from("Couldn't parse kind"))?;
-
-      // Parse condition
-      let cond = Condition::lookup(&chars_html);
-
-      // Parse bedrooms
-      let n_bedrooms = if Regex::new(r"[Ss]tudio")
-        .expect("INTERNAL ERROR: Couldn't parse regex")
-        .find(&chars_html)
-        .is_some()
-      {
-        Some(0)
-      } else {
-        let re_bedrooms =
-          Regex::new(r"[Bb]edrooms*").expect("INTERNAL ERROR: Couldn't parse regex");
-        match chars
-          .select(&li_sel)
-          .filter(|li| re_bedrooms.find(&li.inner_html()).is_some())
-          .next()
-        {
-          Some(li) => Some(
-            li.select(&a_sel)
-              .next()
-              .ok_or(Error::from("Couldn't select bedrooms"))?
-              .inner_html()
-              .trim()
-              .parse()
-              .map_err(|e| Error::from(format!("Couldn't parse bedrooms:{}", e)))?,
-          ),
-          None => None,
-        }
-      };
-
-      // Parse bathrooms
-      let re_bathrooms =
-        Regex::new(r"[Bb]athrooms*").expect("INTERNAL ERROR: Couldn't parse regex");
-      let n_bathrooms = chars
-        .select(&li_sel)
-        .filter(|li| re_bathrooms.find(&li.inner_html()).is_some())
-        .next()
-        .map(|li| {
-          li.select(&span_sel)
-            .filter(|span| re_int.find(&span.inner_html()).is_some())
-            .next()
-            .map(|span| {
-              let str_bathrooms = span.inner_html();
-              str_bathrooms.trim().parse().ok()
-            })
-        })
-        .flatten()
-        .flatten();
-
-      // Parse post code
-      let re_post =
-        Regex::new(r"[Pp]ostal\s+[Cc]ode").expect("INTERNAL ERROR: Couldn't parse regex");
-      let post_code = chars
-        .select(&li_sel)
-        .filter(|li| re_post.find(&li.inner_html()).is_some())
-        .next()
-        .map(|li| {
-          li.select(&span_sel)
-            .filter(|span| re_int.find(&span.inner_html()).is_some())
-            .next()
-            .map(|span| {
-              let str_post_code = span.inner_html();
-              str_post_code.trim().parse().ok()
-            })
-        })
-        .flatten()
-        .flatten();
-
-      // Parse year
-      let re_year = Regex::new(r"(20[0-3][0-9])|(19[0-9][0-9])").expect("Couldn't parse regex");
-      let year = re_year
-        .captures_iter(&desc_html)
-        .filter_map(|c| c[0].parse::<u32>().ok())
-        .min();
-
-      Ok(Listing::Property(Property::new(
-        id,
-        url.clone(),
-        Website::Bazaraki,
-        timestamp,
-        kind,
-        price,
-        area,
-        size,
-        cond,
-        year,
-        n_bedrooms,
-        n_bathrooms,
-        post_code,
-      )))
-    }
-    ListingKind::Plot => {
-      // Parse plot kind
-      let kind = PlotKind::lookup(&chars_html);
-
-      // Parse coverage
-      let coverage = parse_coverage(&desc_html)?;
-
-      // Parse density
-      let density = parse_density(&desc_html)?;
-
-      // Parse height
-      let height = parse_height(&desc_html)?;
+/// Collects an element's text with its whitespace collapsed
+pub fn text(element: &ElementRef) -> String {
+  element
+    .text()
+    .flat_map(|t| t.split_whitespace())
+    .collect::<Vec<&str>>()
+    .join(" ")
+}
 
-      // Parse storeys
-      let storeys = parse_storeys(&desc_html)?;
+/// Reads the plain text of every element matching `selector`, as the description of a listing
+pub fn parse_description(html: &Html, selector: &str) -> Option<String> {
+  let sel = Selector::parse(selector).expect("INTERNAL ERROR: Couldn't parse selector");
+  let description = html
+    .select(&sel)
+    .map(|element| text(&element))
+    .filter(|text| !text.is_empty())
+    .collect::<Vec<String>>()
+    .join(" ");
+  Some(description).filter(|description| !description.is_empty())
+}
 
-      Ok(Listing::Plot(Plot::new(
-        id,
-        url.clone(),
-        Website::Bazaraki,
-        timestamp,
-        price,
-        area,
-        kind,
-        size,
-        coverage,
-        density,
-        height,
-        storeys,
-      )))
+/// Collects the URLs of the pictures marked with `itemprop="image"`, in order and without repeats
+pub fn parse_images(html: &Html, url: &Url) -> Vec<Url> {
+  let sel =
+    Selector::parse("[itemprop=\"image\"]").expect("INTERNAL ERROR: Couldn't parse selector");
+  let mut images: Vec<Url> = vec![];
+  for element in html.select(&sel) {
+    let value = element.value();
+    let src = match ["content", "data-src", "src", "href"]
+      .iter()
+      .find_map(|attr| value.attr(attr).filter(|src| !src.trim().is_empty()))
+    {
+      Some(src) => src.trim(),
+      None => continue,
+    };
+    match url.join(src) {
+      Ok(image) if !images.contains(&image) => images.push(image),
+      Ok(_) => (),
+      Err(e) => error!("Couldn't parse {} as URL:{}", src, e),
     }
   }
+  images
+}
+
+/// Parses the leading number of a value such as `€ 150,000` or `1.250 m²`, ignoring thousands
+/// separators and spaces
+pub fn parse_integer(from: &str) -> Option<u32> {
+  let digits: String = from
+    .chars()
+    .skip_while(|c| !c.is_ascii_digit())
+    .take_while(|c| c.is_ascii_digit() || *c == ',' || *c == '.' || *c == ' ')
+    .filter(|c| c.is_ascii_digit())
+    .collect();
+  digits.parse().ok()
 }
 
-fn parse_coverage(from: &str) -> Result<Option<u32>, Error> {
+pub fn parse_coverage(from: &str) -> Result<Option<u32>, Error> {
   Ok(
     if let Some(caps) = RegexBuilder::new(
       r"([0-9]+)\s*%(\s+max(imum)?)?(\s+build(ing)?)?\s+cover(age)?(\s+((coefficient)|(factor)))?",
@@ -288,7 +120,7 @@ fn parse_coverage(from: &str) -> Result<Option<u32>, Error> {
   )
 }
 
-fn parse_density(from: &str) -> Result<Option<u32>, Error> {
+pub fn parse_density(from: &str) -> Result<Option<u32>, Error> {
   Ok(
   if let Some(caps) = RegexBuilder::new(
       r"([0-9]+)\s*%(\s+max(imum)?)?\s*(((build(ing)?)?\s*density)|(build(ing)?\s*(density)?))",
@@ -347,7 +179,7 @@ fn parse_density(from: &str) -> Result<Option<u32>, Error> {
     })
 }
 
-fn parse_height(from: &str) -> Result<Option<f32>, Error> {
+pub fn parse_height(from: &str) -> Result<Option<f32>, Error> {
   Ok(
     if let Some(caps) = RegexBuilder::new(r"([0-9]+([.,][0-9]+)?)\s*((m)|(meter)|(mtr))(s)?\s*(max(imum)?)?\s*((permitted)|(allowed))?\s*((height)|(high))")
       .case_insensitive(true)
@@ -403,7 +235,7 @@ fn parse_height(from: &str) -> Result<Option<f32>, Error> {
   )
 }
 
-fn parse_storeys(from: &str) -> Result<Option<u32>, Error> {
+pub fn parse_storeys(from: &str) -> Result<Option<u32>, Error> {
   Ok(
     if let Some(caps) =
       RegexBuilder::new(r"([0-9]+)\s*(max(imum)?)?\s*((floors?)|(storeys?)|([όο]ρ[όο]φο(υς)?))")
@@ -427,49 +259,39 @@ fn parse_storeys(from: &str) -> Result<Option<u32>, Error> {
 #[cfg(test)]
 mod test {
   use super::*;
-  use scraper::Html;
-  use std::fs::File;
-  use std::io::Read;
-  use std::str::FromStr;
 
   #[test]
-  fn bazaraki_parser() {
-    let paths = vec![
-      "res/listing_1.html",
-      "res/listing_2.html",
-      "res/listing_3.html",
-      "res/listing_4.html",
-      "res/listing_5.html",
-      "res/listing_6.html",
-      "res/listing_7.html",
-      "res/listing_8.html",
-      "res/listing_9.html",
-      "res/listing_10.html",
-      "res/listing_11.html",
-      "res/listing_12.html",
-      "res/listing_13.html",
-    ];
-
-    for path in paths.iter() {
-      let mut content = String::new();
-      let mut file = File::open(path)
-        .or(Err(format!("Couldn't open {}", path)))
-        .unwrap();
-      file
-        .read_to_string(&mut content)
-        .or(Err(format!("Couldn't read {}", path)))
-        .unwrap();
-      let document = Html::parse_document(&content);
+  fn images_parser() {
+    let html = Html::parse_document(
+      r#"<meta itemprop="image" content="https://cdn.example.com/1.jpg">
+      <img itemprop="image" src="./files/2.jpg"><img itemprop="image" data-src="/3.jpg" src="">
+      <img itemprop="image" src="https://cdn.example.com/1.jpg"><img src="logo.png">"#,
+    );
+    let url = Url::parse("https://www.example.com/adv/123_house/").unwrap();
+    let images: Vec<String> = parse_images(&html, &url)
+      .iter()
+      .map(|url| url.to_string())
+      .collect();
+    assert_eq!(
+      images,
+      vec![
+        "https://cdn.example.com/1.jpg",
+        "https://www.example.com/adv/123_house/files/2.jpg",
+        "https://www.example.com/3.jpg",
+      ]
+    );
+  }
 
-      println!(
-        "{:?}\n",
-        parse_bazaraki(
-          &document,
-          &Url::from_str(&format!("https://foo.bar/{}", path)).unwrap()
-        )
-        .expect("Couldn't parse bazaraki listing")
-      );
-    }
+  #[test]
+  fn description_parser() {
+    let html = Html::parse_document(
+      "<div class=\"desc\"><h3>Description</h3><p>Sea  view &amp; <b>pool</b></p><p></p>\n<p>near\n  the beach</p></div>",
+    );
+    assert_eq!(
+      parse_description(&html, "div.desc > p").as_deref(),
+      Some("Sea view & pool near the beach")
+    );
+    assert_eq!(parse_description(&html, "div.missing"), None);
   }
 
   #[test]
@@ -1,18 +1,24 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDateTime, Utc};
 use regex::Regex;
 use reqwest::Url;
 
 use std::str::FromStr;
 
 use crate::area::Area;
-use crate::io::{timestamp_deserializer, timestamp_serializer, url_deserializer, url_serializer};
+use crate::io::{
+  local_time_deserializer, local_time_serializer, timestamp_deserializer, timestamp_serializer,
+  url_deserializer, url_serializer,
+};
 use crate::lookup::Lookup;
+use crate::precision::Precision;
+use crate::seller::Seller;
 use crate::site::Website;
+use crate::status::Status;
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct Plot {
   /// Unique Identifier
-  id: String,
+  pub id: String,
   /// URL
   #[serde(
     serialize_with = "url_serializer",
@@ -43,6 +49,47 @@ pub struct Plot {
   height: Option<f32>,
   /// Maximum permitted number of storeys
   storeys: Option<u32>,
+  /// Lifecycle status
+  pub status: Status,
+  /// When the listing was first seen
+  #[serde(
+    serialize_with = "timestamp_serializer",
+    deserialize_with = "timestamp_deserializer"
+  )]
+  pub first_seen: DateTime<Utc>,
+  /// When the listing was last seen live
+  #[serde(
+    serialize_with = "timestamp_serializer",
+    deserialize_with = "timestamp_deserializer"
+  )]
+  pub last_seen: DateTime<Utc>,
+  /// When the listing was posted, in the site's local time
+  #[serde(
+    serialize_with = "local_time_serializer",
+    deserialize_with = "local_time_deserializer"
+  )]
+  pub posted_at: Option<NaiveDateTime>,
+  /// # of views
+  pub views: Option<u32>,
+  /// Title
+  pub title: Option<String>,
+  /// Latitude
+  pub lat: Option<f64>,
+  /// Longitude
+  pub lon: Option<f64>,
+  /// Whether the coordinates are those of the listing itself or of its neighbourhood
+  pub precision: Option<Precision>,
+  /// UID of the seller, as found in `sellers.csv`
+  pub seller_id: Option<String>,
+  /// Description, kept in `descriptions.csv` rather than alongside the other fields
+  #[serde(skip)]
+  pub description: Option<String>,
+  /// URLs of the pictures, in the order they're shown, kept in `images.csv`
+  #[serde(skip)]
+  pub images: Vec<Url>,
+  /// Seller, kept in `sellers.csv`
+  #[serde(skip)]
+  pub seller: Option<Seller>,
 }
 
 impl Plot {
@@ -73,6 +120,19 @@ impl Plot {
       density,
       height,
       storeys,
+      status: Status::Active,
+      first_seen: timestamp,
+      last_seen: timestamp,
+      posted_at: None,
+      views: None,
+      title: None,
+      lat: None,
+      lon: None,
+      precision: None,
+      seller_id: None,
+      description: None,
+      images: vec![],
+      seller: None,
     }
   }
 }
@@ -94,6 +154,19 @@ impl Default for Plot {
       density: Some(40),
       height: Some(10.2),
       storeys: Some(1),
+      status: Status::Active,
+      first_seen: Utc::now(),
+      last_seen: Utc::now(),
+      posted_at: None,
+      views: None,
+      title: None,
+      lat: None,
+      lon: None,
+      precision: None,
+      seller_id: None,
+      description: None,
+      images: vec![],
+      seller: None,
     };
   }
 }
@@ -0,0 +1,8 @@
+/// How closely a listing's coordinates pin it down
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub enum Precision {
+  /// The pin the seller put on the map
+  Exact,
+  /// Only the neighbourhood the map is centered on
+  Approximate,
+}
@@ -0,0 +1,523 @@
+use chrono::{DateTime, Duration as TimeDelta, NaiveDateTime, TimeZone, Utc};
+use rand::seq::SliceRandom;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, ACCEPT_LANGUAGE, COOKIE, SET_COOKIE};
+use reqwest::{Client, Proxy, Url};
+use structopt::StructOpt;
+
+use std::collections::BTreeMap;
+use std::fs::{read_to_string, rename, File};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::error::Error;
+use crate::io::data_path;
+
+const DEFAULT_USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/70.0.3538.77 Safari/537.36";
+
+/// The longest a cookie is kept for, whatever its `Max-Age`
+const MAX_COOKIE_AGE_DAYS: i64 = 400;
+
+#[derive(Debug, StructOpt)]
+pub struct ProfileArgs {
+  #[structopt(
+    long = "proxy",
+    help = "Send every request through this proxy, e.g. http://localhost:8080, or socks5://localhost:1080 if built with the socks feature"
+  )]
+  pub proxy: Option<String>,
+
+  #[structopt(
+    long = "connect-timeout",
+    help = "How long (in milliseconds) to wait for a connection to be established"
+  )]
+  pub connect_timeout: Option<u64>,
+
+  #[structopt(
+    long = "read-timeout",
+    help = "How long (in milliseconds) to wait for a response or its body"
+  )]
+  pub read_timeout: Option<u64>,
+
+  #[structopt(
+    short = "H",
+    long = "header",
+    number_of_values = 1,
+    help = "An extra header to send with every request, e.g. 'DNT: 1'. Can be repeated"
+  )]
+  pub headers: Vec<String>,
+
+  #[structopt(
+    long = "user-agent",
+    number_of_values = 1,
+    help = "A User-Agent to pick from at random for every request. Can be repeated"
+  )]
+  pub user_agents: Vec<String>,
+
+  #[structopt(
+    long = "accept-language",
+    help = "The preferred language of the pages, e.g. 'en-US,en;q=0.5'"
+  )]
+  pub accept_language: Option<String>,
+
+  #[structopt(
+    long = "cookies",
+    help = "Keep cookies between requests and runs in ~/.truffles/cookies.json"
+  )]
+  pub cookies: bool,
+}
+
+/// How the HTTP client presents itself, set from the `[client]` table of `~/.truffles/config.toml`
+/// and overridden from the command line
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+  /// HTTP(S) or SOCKS5 proxy URL
+  pub proxy: Option<String>,
+  /// Connect timeout in milliseconds
+  pub connect_timeout: Option<u64>,
+  /// Read timeout in milliseconds
+  pub read_timeout: Option<u64>,
+  /// Extra headers
+  pub headers: BTreeMap<String, String>,
+  /// User agents to rotate through
+  pub user_agents: Vec<String>,
+  /// `Accept-Language` header
+  pub accept_language: Option<String>,
+  /// Whether to keep a persistent cookie jar
+  pub cookies: bool,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct Config {
+  client: Profile,
+}
+
+impl Profile {
+  /// Loads the profile from the config file, if there is one
+  pub fn load() -> Result<Self, Error> {
+    let path = data_path("config.toml");
+    if !path.exists() {
+      return Ok(Self::default());
+    }
+    let content = read_to_string(&path).map_err(Error::from)?;
+    toml::from_str::<Config>(&content)
+      .map(|config| config.client)
+      .map_err(|e| Error::from(format!("Couldn't parse {}:{}", path.display(), e)))
+  }
+
+  /// Overrides the profile with whatever was given on the command line
+  pub fn with_args(mut self, args: &ProfileArgs) -> Result<Self, Error> {
+    if args.proxy.is_some() {
+      self.proxy = args.proxy.clone();
+    }
+    if args.connect_timeout.is_some() {
+      self.connect_timeout = args.connect_timeout;
+    }
+    if args.read_timeout.is_some() {
+      self.read_timeout = args.read_timeout;
+    }
+    for header in args.headers.iter() {
+      let (name, value) = parse_header(header)?;
+      self.headers.insert(name, value);
+    }
+    if !args.user_agents.is_empty() {
+      self.user_agents = args.user_agents.clone();
+    }
+    if args.accept_language.is_some() {
+      self.accept_language = args.accept_language.clone();
+    }
+    self.cookies |= args.cookies;
+    Ok(self)
+  }
+
+  /// Builds a client that sends the profile's proxy, timeouts and headers
+  pub fn client(&self) -> Result<Client, Error> {
+    let mut headers = HeaderMap::new();
+    if let Some(accept_language) = &self.accept_language {
+      headers.insert(
+        ACCEPT_LANGUAGE,
+        HeaderValue::from_str(accept_language).map_err(Error::from)?,
+      );
+    }
+    for (name, value) in self.headers.iter() {
+      headers.insert(
+        HeaderName::from_bytes(name.as_bytes()).map_err(Error::from)?,
+        HeaderValue::from_str(value).map_err(Error::from)?,
+      );
+    }
+
+    let mut builder = Client::builder().default_headers(headers);
+    if let Some(proxy) = &self.proxy {
+      check_proxy(proxy)?;
+      builder = builder.proxy(
+        Proxy::all(proxy)
+          .map_err(|e| Error::from(format!("Couldn't set up proxy {}:{}", proxy, e)))?,
+      );
+    }
+    if let Some(timeout) = self.connect_timeout {
+      builder = builder.connect_timeout(Duration::from_millis(timeout));
+    }
+    builder.build().map_err(Error::from)
+  }
+
+  pub fn read_timeout(&self) -> Option<Duration> {
+    self.read_timeout.map(Duration::from_millis)
+  }
+
+  /// Picks a user agent out of the pool
+  pub fn user_agent(&self) -> &str {
+    self
+      .user_agents
+      .choose(&mut rand::thread_rng())
+      .map(String::as_str)
+      .unwrap_or(DEFAULT_USER_AGENT)
+  }
+}
+
+/// Splits a `Name: value` header
+fn parse_header(header: &str) -> Result<(String, String), Error> {
+  match header.split_once(':') {
+    Some((name, value)) if !name.trim().is_empty() => {
+      Ok((name.trim().to_string(), value.trim().to_string()))
+    }
+    _ => Err(Error::from(format!(
+      "Expected a header like 'Name: value', got '{}'",
+      header
+    ))),
+  }
+}
+
+/// Checks that the proxy is a URL of a kind the client can go through
+fn check_proxy(proxy: &str) -> Result<(), Error> {
+  let url = Url::parse(proxy)
+    .map_err(|e| Error::from(format!("Couldn't parse proxy URL {}:{}", proxy, e)))?;
+  match url.scheme() {
+    "http" | "https" => Ok(()),
+    "socks5" | "socks5h" if cfg!(feature = "socks") => Ok(()),
+    "socks5" | "socks5h" => Err(Error::from(format!(
+      "SOCKS proxies such as {} need truffles to be built with `--features socks`",
+      proxy
+    ))),
+    scheme => Err(Error::from(format!(
+      "Unsupported proxy scheme {} in {}",
+      scheme, proxy
+    ))),
+  }
+}
+
+/// A cookie as kept in the jar
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+struct Cookie {
+  value: String,
+  /// Whether the cookie only goes back to the host that set it, rather than to its whole domain
+  host_only: bool,
+  /// When the cookie expires, as a UNIX timestamp, unless it lasts until it's replaced
+  expires: Option<i64>,
+}
+
+impl Cookie {
+  fn is_expired(&self, now: DateTime<Utc>) -> bool {
+    matches!(self.expires, Some(expires) if expires <= now.timestamp())
+  }
+}
+
+/// Cookies by domain, persisted in `~/.truffles/cookies.json`
+///
+/// This is deliberately simpler than a browser's jar: out of their attributes, only `Domain`,
+/// `Expires` and `Max-Age` are honoured, and there's no list of public suffixes to keep cookies
+/// from being set for a whole top-level domain.
+pub struct CookieJar {
+  cookies: Mutex<BTreeMap<String, BTreeMap<String, Cookie>>>,
+}
+
+impl CookieJar {
+  pub fn load() -> Result<Self, Error> {
+    let cookies = match read_to_string(data_path("cookies.json")) {
+      Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+        warn!("Couldn't read saved cookies, starting without any:{}", e);
+        BTreeMap::new()
+      }),
+      Err(_) => BTreeMap::new(),
+    };
+    Ok(Self {
+      cookies: Mutex::new(cookies),
+    })
+  }
+
+  /// The `Cookie` header to send to `url`, if there are any live cookies for its host
+  pub fn header(&self, url: &Url) -> Option<(HeaderName, String)> {
+    let value = self.cookies_for(url.host_str()?, Utc::now())?;
+    Some((COOKIE, value))
+  }
+
+  /// The cookies to send to `host` as of `now`, as the value of a `Cookie` header
+  fn cookies_for(&self, host: &str, now: DateTime<Utc>) -> Option<String> {
+    let cookies = self
+      .cookies
+      .lock()
+      .expect("INTERNAL ERROR: Cookie jar lock poisoned");
+    let pairs: Vec<String> = cookies
+      .iter()
+      .filter(|(domain, _)| domain_matches(host, domain))
+      .flat_map(|(domain, cookies)| {
+        cookies
+          .iter()
+          .filter(move |(_, cookie)| !cookie.host_only || host == domain.as_str())
+      })
+      .filter(|(_, cookie)| !cookie.is_expired(now))
+      .map(|(name, cookie)| format!("{}={}", name, cookie.value))
+      .collect();
+    if pairs.is_empty() {
+      None
+    } else {
+      Some(pairs.join("; "))
+    }
+  }
+
+  /// Takes in the cookies set by a response, saving the jar if anything changed
+  pub fn update(&self, url: &Url, headers: &HeaderMap) -> Result<(), Error> {
+    let host = match url.host_str() {
+      Some(host) => host,
+      None => return Ok(()),
+    };
+    let set_cookies: Vec<&str> = headers
+      .get_all(SET_COOKIE)
+      .iter()
+      .filter_map(|value| value.to_str().ok())
+      .collect();
+    if self.take(host, &set_cookies, Utc::now()) {
+      self.save()
+    } else {
+      Ok(())
+    }
+  }
+
+  /// Takes in `Set-Cookie` headers from `host` as of `now`, telling whether anything changed
+  fn take(&self, host: &str, set_cookies: &[&str], now: DateTime<Utc>) -> bool {
+    let mut cookies = self
+      .cookies
+      .lock()
+      .expect("INTERNAL ERROR: Cookie jar lock poisoned");
+    let mut changed = false;
+    for set_cookie in set_cookies
+      .iter()
+      .filter_map(|header| parse_set_cookie(header, now))
+    {
+      // A cookie can be set for a domain the host belongs to, but not for any other
+      let (domain, host_only) = match set_cookie.domain {
+        Some(domain) if domain_matches(host, &domain) => (domain, false),
+        Some(domain) => {
+          warn!(
+            "Ignoring cookie {} set by {} for {}",
+            set_cookie.name, host, domain
+          );
+          continue;
+        }
+        None => (host.to_ascii_lowercase(), true),
+      };
+      let cookie = Cookie {
+        value: set_cookie.value,
+        host_only,
+        expires: set_cookie.expires.map(|expires| expires.timestamp()),
+      };
+      let domain_cookies = cookies.entry(domain).or_default();
+      if cookie.value.is_empty() || cookie.is_expired(now) {
+        changed |= domain_cookies.remove(&set_cookie.name).is_some();
+      } else {
+        changed |= domain_cookies.insert(set_cookie.name, cookie.clone()) != Some(cookie);
+      }
+    }
+
+    // Expired cookies are let go of along the way
+    for domain_cookies in cookies.values_mut() {
+      let before = domain_cookies.len();
+      domain_cookies.retain(|_, cookie| !cookie.is_expired(now));
+      changed |= domain_cookies.len() != before;
+    }
+    cookies.retain(|_, domain_cookies| !domain_cookies.is_empty());
+    changed
+  }
+
+  fn save(&self) -> Result<(), Error> {
+    let cookies = self
+      .cookies
+      .lock()
+      .expect("INTERNAL ERROR: Cookie jar lock poisoned");
+    let path = data_path("cookies.json");
+    let tmp_path = path.with_extension("tmp");
+    serde_json::to_writer(File::create(&tmp_path).map_err(Error::from)?, &*cookies)
+      .map_err(Error::from)?;
+    rename(tmp_path, path).map_err(Error::from)
+  }
+}
+
+/// Whether `host` is `domain` or one of its subdomains
+fn domain_matches(host: &str, domain: &str) -> bool {
+  let (host, domain) = (host.to_ascii_lowercase(), domain.to_ascii_lowercase());
+  host == domain || host.ends_with(&format!(".{}", domain))
+}
+
+/// A cookie as set by a response
+#[derive(Debug, PartialEq)]
+struct SetCookie {
+  name: String,
+  /// Empty if the cookie is being deleted
+  value: String,
+  /// The domain the cookie is for, if it's not only for the host that set it
+  domain: Option<String>,
+  expires: Option<DateTime<Utc>>,
+}
+
+/// Parses a `Set-Cookie` header received at `now`
+fn parse_set_cookie(header: &str, now: DateTime<Utc>) -> Option<SetCookie> {
+  let mut parts = header.split(';');
+  let (name, value) = parts.next()?.split_once('=')?;
+  let (name, value) = (name.trim(), value.trim());
+  if name.is_empty() {
+    return None;
+  }
+
+  let (mut domain, mut max_age, mut expires) = (None, None, None);
+  for attr in parts {
+    let (key, val) = attr.split_once('=').unwrap_or((attr, ""));
+    let (key, val) = (key.trim(), val.trim());
+    if key.eq_ignore_ascii_case("domain") && !val.is_empty() {
+      domain = Some(val.trim_start_matches('.').to_ascii_lowercase());
+    } else if key.eq_ignore_ascii_case("max-age") {
+      max_age = val.parse::<i64>().ok();
+    } else if key.eq_ignore_ascii_case("expires") {
+      expires = parse_cookie_date(val);
+    }
+  }
+  // `Max-Age` takes precedence over `Expires`
+  let expires = match max_age {
+    Some(seconds) => Some(now + TimeDelta::seconds(seconds.clamp(0, MAX_COOKIE_AGE_DAYS * 86400))),
+    None => expires,
+  };
+
+  Some(SetCookie {
+    name: name.to_string(),
+    value: value.to_string(),
+    domain,
+    expires,
+  })
+}
+
+/// Parses the date of an `Expires` attribute, e.g. `Wed, 21 Oct 2015 07:28:00 GMT`
+fn parse_cookie_date(date: &str) -> Option<DateTime<Utc>> {
+  DateTime::parse_from_rfc2822(date)
+    .map(|date| date.with_timezone(&Utc))
+    .ok()
+    .or_else(|| {
+      NaiveDateTime::parse_from_str(date, "%a, %d-%b-%Y %H:%M:%S GMT")
+        .ok()
+        .map(|date| Utc.from_utc_datetime(&date))
+    })
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn set_cookie_parser() {
+    let now = Utc.with_ymd_and_hms(2020, 11, 20, 10, 30, 0).unwrap();
+    let cookie = parse_set_cookie("sessionid=abc123; Path=/; HttpOnly", now).unwrap();
+    assert_eq!(cookie.name, "sessionid");
+    assert_eq!(cookie.value, "abc123");
+    assert_eq!((cookie.domain, cookie.expires), (None, None));
+
+    let cookie = parse_set_cookie(
+      "id=1; Domain=.Bazaraki.com; Expires=Wed, 21 Oct 2015 07:28:00 GMT",
+      now,
+    )
+    .unwrap();
+    assert_eq!(cookie.domain.as_deref(), Some("bazaraki.com"));
+    assert_eq!(
+      cookie.expires,
+      Some(Utc.with_ymd_and_hms(2015, 10, 21, 7, 28, 0).unwrap())
+    );
+    assert_eq!(
+      parse_set_cookie("id=1; expires=Wed, 21-Oct-2015 07:28:00 GMT", now)
+        .unwrap()
+        .expires,
+      Some(Utc.with_ymd_and_hms(2015, 10, 21, 7, 28, 0).unwrap())
+    );
+
+    // Max-Age wins over Expires
+    let cookie = parse_set_cookie(
+      "id=1; Max-Age=60; Expires=Wed, 21 Oct 2015 07:28:00 GMT",
+      now,
+    )
+    .unwrap();
+    assert_eq!(cookie.expires, Some(now + TimeDelta::seconds(60)));
+    assert_eq!(
+      parse_set_cookie("id=1; Max-Age=0", now).unwrap().expires,
+      Some(now)
+    );
+    assert_eq!(parse_set_cookie("garbage", now), None);
+
+    assert_eq!(
+      parse_header("Accept: text/html").unwrap(),
+      (String::from("Accept"), String::from("text/html"))
+    );
+    assert!(parse_header("text/html").is_err());
+  }
+
+  #[test]
+  fn cookie_jar() {
+    let now = Utc.with_ymd_and_hms(2020, 11, 20, 10, 30, 0).unwrap();
+    let jar = CookieJar {
+      cookies: Mutex::new(BTreeMap::new()),
+    };
+    assert!(jar.take(
+      "www.bazaraki.com",
+      &[
+        "a=1; Domain=bazaraki.com",
+        "b=2",
+        "c=3; Max-Age=0",
+        "d=4; Expires=Wed, 21 Oct 2015 07:28:00 GMT",
+        "e=5; Domain=spitogatos.gr",
+        "f=6; Max-Age=60",
+      ],
+      now,
+    ));
+    assert_eq!(
+      jar.cookies_for("www.bazaraki.com", now).as_deref(),
+      Some("a=1; b=2; f=6")
+    );
+    // Only cookies set for the whole domain go to its other hosts
+    assert_eq!(jar.cookies_for("bazaraki.com", now).as_deref(), Some("a=1"));
+    assert_eq!(
+      jar.cookies_for("m.bazaraki.com", now).as_deref(),
+      Some("a=1")
+    );
+    assert_eq!(jar.cookies_for("notbazaraki.com", now), None);
+    assert_eq!(jar.cookies_for("www.spitogatos.gr", now), None);
+
+    // Cookies go away once they expire or get deleted
+    let later = now + TimeDelta::seconds(120);
+    assert_eq!(
+      jar.cookies_for("www.bazaraki.com", later).as_deref(),
+      Some("a=1; b=2")
+    );
+    assert!(jar.take("www.bazaraki.com", &["b=; Path=/"], later));
+    assert_eq!(
+      jar.cookies_for("www.bazaraki.com", later).as_deref(),
+      Some("a=1")
+    );
+    assert!(!jar.take("www.bazaraki.com", &["a=1; Domain=bazaraki.com"], later));
+  }
+
+  #[test]
+  fn proxy_schemes() {
+    assert!(check_proxy("http://localhost:8080").is_ok());
+    assert!(check_proxy("https://localhost:8443").is_ok());
+    assert_eq!(
+      check_proxy("socks5://localhost:1080").is_ok(),
+      cfg!(feature = "socks")
+    );
+    assert!(check_proxy("ftp://localhost:21").is_err());
+    assert!(check_proxy("localhost:8080").is_err());
+  }
+}
@@ -1,4 +1,4 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDateTime, Utc};
 use regex::Regex;
 use reqwest::Url;
 
@@ -6,14 +6,20 @@ use std::str::FromStr;
 
 use crate::area::Area;
 use crate::cond::Condition;
-use crate::io::{timestamp_deserializer, timestamp_serializer, url_deserializer, url_serializer};
+use crate::io::{
+  local_time_deserializer, local_time_serializer, timestamp_deserializer, timestamp_serializer,
+  url_deserializer, url_serializer,
+};
 use crate::lookup::Lookup;
+use crate::precision::Precision;
+use crate::seller::Seller;
 use crate::site::Website;
+use crate::status::Status;
 
-#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct Property {
   /// Unique Identifier
-  id: String,
+  pub id: String,
   /// URL
   #[serde(
     serialize_with = "url_serializer",
@@ -46,6 +52,47 @@ pub struct Property {
   n_bathrooms: Option<u8>,
   /// Postal Code
   post_code: Option<u32>,
+  /// Lifecycle status
+  pub status: Status,
+  /// When the listing was first seen
+  #[serde(
+    serialize_with = "timestamp_serializer",
+    deserialize_with = "timestamp_deserializer"
+  )]
+  pub first_seen: DateTime<Utc>,
+  /// When the listing was last seen live
+  #[serde(
+    serialize_with = "timestamp_serializer",
+    deserialize_with = "timestamp_deserializer"
+  )]
+  pub last_seen: DateTime<Utc>,
+  /// When the listing was posted, in the site's local time
+  #[serde(
+    serialize_with = "local_time_serializer",
+    deserialize_with = "local_time_deserializer"
+  )]
+  pub posted_at: Option<NaiveDateTime>,
+  /// # of views
+  pub views: Option<u32>,
+  /// Title
+  pub title: Option<String>,
+  /// Latitude
+  pub lat: Option<f64>,
+  /// Longitude
+  pub lon: Option<f64>,
+  /// Whether the coordinates are those of the listing itself or of its neighbourhood
+  pub precision: Option<Precision>,
+  /// UID of the seller, as found in `sellers.csv`
+  pub seller_id: Option<String>,
+  /// Description, kept in `descriptions.csv` rather than alongside the other fields
+  #[serde(skip)]
+  pub description: Option<String>,
+  /// URLs of the pictures, in the order they're shown, kept in `images.csv`
+  #[serde(skip)]
+  pub images: Vec<Url>,
+  /// Seller, kept in `sellers.csv`
+  #[serde(skip)]
+  pub seller: Option<Seller>,
 }
 
 impl Property {
@@ -78,6 +125,19 @@ impl Property {
       n_bedrooms,
       n_bathrooms,
       post_code,
+      status: Status::Active,
+      first_seen: timestamp,
+      last_seen: timestamp,
+      posted_at: None,
+      views: None,
+      title: None,
+      lat: None,
+      lon: None,
+      precision: None,
+      seller_id: None,
+      description: None,
+      images: vec![],
+      seller: None,
     }
   }
 }
@@ -98,6 +158,19 @@ impl Default for Property {
       n_bedrooms: Some(1),
       n_bathrooms: Some(1),
       post_code: Some(2020),
+      status: Status::Active,
+      first_seen: Utc::now(),
+      last_seen: Utc::now(),
+      posted_at: None,
+      views: None,
+      title: None,
+      lat: None,
+      lon: None,
+      precision: None,
+      seller_id: None,
+      description: None,
+      images: vec![],
+      seller: None,
     };
   }
 }
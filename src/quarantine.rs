@@ -0,0 +1,262 @@
+use chrono::{DateTime, Utc};
+use csv::{Reader, Writer, WriterBuilder};
+use reqwest::{StatusCode, Url};
+use scraper::Html;
+use sha2::{Digest, Sha256};
+use structopt::StructOpt;
+
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, read_to_string, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::error::Error;
+use crate::io::{
+  data_path, timestamp_deserializer, timestamp_serializer, url_deserializer, url_serializer,
+};
+use crate::replay::Recording;
+use crate::sites::Registry;
+
+#[derive(Debug, StructOpt)]
+pub struct QuarantineArgs {
+  #[structopt(
+    short = "p",
+    long = "promote",
+    help = "Copy the quarantined page with this ID into the fixtures as a new parser test case, once the parser handles it"
+  )]
+  pub promote: Option<String>,
+
+  #[structopt(
+    long = "fixtures",
+    parse(from_os_str),
+    default_value = "res",
+    help = "The directory of fixtures to promote pages into"
+  )]
+  pub fixtures: PathBuf,
+}
+
+/// Index record of a listing page that couldn't be parsed
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Failure {
+  /// URL
+  #[serde(
+    serialize_with = "url_serializer",
+    deserialize_with = "url_deserializer"
+  )]
+  pub url: Url,
+  /// Time of the failure
+  #[serde(
+    serialize_with = "timestamp_serializer",
+    deserialize_with = "timestamp_deserializer"
+  )]
+  pub timestamp: DateTime<Utc>,
+  /// What the parser had to say
+  pub error: String,
+  /// SHA-256 of the page, which also names its file
+  pub hash: String,
+}
+
+impl Failure {
+  /// A short ID to refer to the page by
+  pub fn id(&self) -> &str {
+    &self.hash[..12]
+  }
+}
+
+/// Listing pages that couldn't be parsed, kept under `~/.truffles/quarantine`
+///
+/// Every page is saved as `<sha256>.html`, while `index.csv` records the URL and the error of every
+/// failure.
+pub struct Quarantine {
+  dir: PathBuf,
+  index: Mutex<Writer<File>>,
+}
+
+impl Quarantine {
+  pub fn open() -> Result<Self, Error> {
+    Self::open_in(data_path("quarantine"))
+  }
+
+  fn open_in(dir: PathBuf) -> Result<Self, Error> {
+    create_dir_all(&dir).map_err(Error::from)?;
+
+    let index_path = dir.join("index.csv");
+    let index = WriterBuilder::new()
+      .has_headers(
+        index_path
+          .metadata()
+          .map(|meta| meta.len() == 0)
+          .unwrap_or(true),
+      )
+      .from_writer(
+        OpenOptions::new()
+          .create(true)
+          .append(true)
+          .open(index_path)
+          .map_err(Error::from)?,
+      );
+
+    Ok(Self {
+      dir,
+      index: Mutex::new(index),
+    })
+  }
+
+  /// Saves a page that couldn't be parsed along with the reason why
+  pub fn put(&self, url: &Url, body: &str, error: &Error) -> Result<(), Error> {
+    let hash = hex::encode(Sha256::digest(body.as_bytes()));
+    let path = self.dir.join(format!("{}.html", hash));
+    if !path.exists() {
+      File::create(&path)
+        .and_then(|mut f| f.write_all(body.as_bytes()))
+        .map_err(Error::from)?;
+    }
+
+    let failure = Failure {
+      url: url.clone(),
+      timestamp: Utc::now(),
+      error: error.to_string(),
+      hash,
+    };
+    let mut index = self
+      .index
+      .lock()
+      .expect("INTERNAL ERROR: Quarantine index lock poisoned");
+    index.serialize(&failure).map_err(Error::from)?;
+    index.flush().map_err(Error::from)
+  }
+
+  /// Reads back every failure, oldest first
+  pub fn failures(&self) -> Result<Vec<Failure>, Error> {
+    Reader::from_path(self.dir.join("index.csv"))
+      .map_err(Error::from)?
+      .into_deserialize()
+      .map(|failure| failure.map_err(Error::from))
+      .collect()
+  }
+
+  /// Reads back a quarantined page
+  pub fn read(&self, failure: &Failure) -> Result<String, Error> {
+    read_to_string(self.dir.join(format!("{}.html", failure.hash))).map_err(Error::from)
+  }
+}
+
+/// Lists quarantined pages grouped by error, or promotes one of them into the fixtures
+pub fn quarantine(args: QuarantineArgs) -> Result<(), String> {
+  let quarantine = Quarantine::open().map_err(|e| format!("Couldn't open quarantine:{}", e))?;
+  let failures = quarantine
+    .failures()
+    .map_err(|e| format!("Couldn't read quarantine index:{}", e))?;
+
+  // Keep the latest failure of every page
+  let mut latest: BTreeMap<String, Failure> = BTreeMap::new();
+  for failure in failures.into_iter() {
+    latest.insert(failure.hash.clone(), failure);
+  }
+
+  if let Some(id) = args.promote {
+    let failure = match latest
+      .values()
+      .filter(|failure| failure.hash.starts_with(&id))
+      .collect::<Vec<&Failure>>()
+      .as_slice()
+    {
+      [failure] => *failure,
+      [] => return Err(format!("No quarantined page with ID {}", id)),
+      _ => return Err(format!("More than one quarantined page with ID {}", id)),
+    };
+    let path = promote(&quarantine, failure, &args.fixtures)
+      .map_err(|e| format!("Couldn't promote {}:{}", id, e))?;
+    println!("Promoted {} to {}", failure.url, path.display());
+    return Ok(());
+  }
+
+  let mut by_error: BTreeMap<&str, Vec<&Failure>> = BTreeMap::new();
+  for failure in latest.values() {
+    by_error.entry(&failure.error).or_default().push(failure);
+  }
+  let mut groups: Vec<(&str, Vec<&Failure>)> = by_error.into_iter().collect();
+  groups.sort_by_key(|(_, failures)| std::cmp::Reverse(failures.len()));
+  for (error, mut failures) in groups.into_iter() {
+    println!("{} ({} pages)", error, failures.len());
+    failures.sort_by_key(|failure| failure.timestamp);
+    for failure in failures.iter() {
+      println!(
+        "  {}  {}  {}",
+        failure.id(),
+        failure.timestamp.format("%F %T"),
+        failure.url
+      );
+    }
+  }
+
+  Ok(())
+}
+
+/// Copies a quarantined page into the fixtures under the next free `<site>_listing_<n>.html` name,
+/// and indexes it so that it can be replayed
+///
+/// Indexed pages are run through the parser tests, so only pages the parser handles by now are
+/// promoted.
+fn promote(quarantine: &Quarantine, failure: &Failure, fixtures: &Path) -> Result<PathBuf, Error> {
+  let registry = Registry::new();
+  let scraper = registry.get_for_url(&failure.url)?;
+  let body = quarantine.read(failure)?;
+  if let Err(e) = scraper.parse_listing(
+    &Html::parse_document(&body),
+    &failure.url,
+    failure.timestamp,
+  ) {
+    return Err(Error::from(format!(
+      "It still can't be parsed, so the parser needs fixing first ({})",
+      e
+    )));
+  }
+
+  let prefix = scraper.fixture_prefix();
+  let file = (1..)
+    .map(|n| format!("{}_{}.html", prefix, n))
+    .find(|file| !fixtures.join(file).exists())
+    .expect("INTERNAL ERROR: Ran out of fixture names");
+
+  Recording::create(fixtures)?.put_file(&failure.url, StatusCode::OK, &file, &body)?;
+  Ok(fixtures.join(file))
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use std::env::temp_dir;
+  use std::fs::remove_dir_all;
+  use std::str::FromStr;
+
+  #[test]
+  fn quarantine_promote() {
+    let dir = temp_dir().join(format!("truffles-quarantine-{}", std::process::id()));
+    let quarantine = Quarantine::open_in(dir.join("quarantine")).unwrap();
+    let fixtures = dir.join("res");
+    let fixed = Url::from_str("https://www.bazaraki.com/adv/1_house/").unwrap();
+    let broken = Url::from_str("https://www.bazaraki.com/adv/2_flat/").unwrap();
+    let page = read_to_string("res/listing_1.html").unwrap();
+    let error = Error::from("Couldn't find price");
+    quarantine.put(&fixed, &page, &error).unwrap();
+    quarantine.put(&broken, "<html>2</html>", &error).unwrap();
+    let failures = quarantine.failures().unwrap();
+
+    // Fixtures are named after their site and numbered, and indexed to be replayed and tested
+    assert_eq!(
+      promote(&quarantine, &failures[0], &fixtures).unwrap(),
+      fixtures.join("listing_1.html")
+    );
+    let recording = Recording::open(&fixtures).unwrap();
+    assert_eq!(recording.get(&fixed).unwrap(), Some((StatusCode::OK, page)));
+
+    // A page the parser still fails on would fail the parser tests
+    assert!(promote(&quarantine, &failures[1], &fixtures).is_err());
+    assert!(!fixtures.join("listing_2.html").exists());
+    assert_eq!(recording.get(&broken).unwrap(), None);
+
+    remove_dir_all(dir).unwrap();
+  }
+}
@@ -0,0 +1,270 @@
+use chrono::{DateTime, Utc};
+use indicatif::{ProgressBar, ProgressStyle};
+use reqwest::Url;
+use scraper::Html;
+use serde_json::Value;
+use structopt::StructOpt;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{copy, create_dir, rename};
+use std::path::{Path, PathBuf};
+
+use crate::archive::{Archive, Entry, Page};
+use crate::cache::{self, Cache};
+use crate::error::Error;
+use crate::io::data_path;
+use crate::listing::Listing;
+use crate::site::Website;
+
+/// How cache generations are named, after the time they were made
+const GENERATION_FORMAT: &str = "%Y%m%dT%H%M%S";
+
+#[derive(Debug, StructOpt)]
+pub struct ReparseArgs {
+  #[structopt(
+    short = "p",
+    long = "promote",
+    help = "Make the cache generation with this name, e.g. 20200601T120000, the current cache, keeping the current one as a generation of its own"
+  )]
+  pub promote: Option<String>,
+}
+
+/// Re-runs the parsers over the latest archived copy of every listing page, writing the results into
+/// a new cache generation under `~/.truffles/generations`, or promotes a generation into the cache
+pub fn reparse(args: ReparseArgs) -> Result<(), String> {
+  if let Some(name) = args.promote {
+    let previous = promote(&data_path(""), &name)
+      .map_err(|e| format!("Couldn't promote generation {}:{}", name, e))?;
+    println!(
+      "Promoted generation {} to the current cache, which was kept as {}",
+      name,
+      previous.display()
+    );
+    return Ok(());
+  }
+
+  let archive = Archive::open().map_err(|e| format!("Couldn't open page archive:{}", e))?;
+  let entries = archive
+    .entries()
+    .map_err(|e| format!("Couldn't read page archive index:{}", e))?;
+
+  // Keep the first and latest successful fetch of every listing (the index is ordered oldest first)
+  let mut latest: HashMap<Url, Entry> = HashMap::new();
+  let mut first_seen: HashMap<Url, DateTime<Utc>> = HashMap::new();
+  for entry in entries.into_iter() {
+    if entry.page == Page::Listing && (200..300).contains(&entry.status) {
+      first_seen
+        .entry(entry.url.clone())
+        .or_insert(entry.timestamp);
+      latest.insert(entry.url.clone(), entry);
+    }
+  }
+
+  let old = Cache::load();
+  let name = Utc::now().format(GENERATION_FORMAT).to_string();
+  let dir = data_path("generations").join(&name);
+  let mut new = Cache::load_from(dir.clone());
+
+  let bar = ProgressBar::new(latest.len() as u64);
+  bar.set_style(
+    ProgressStyle::default_bar()
+      .template("{spinner} Reparsing listings    ... {percent:>3}% (ETA ~{eta})")
+      .tick_chars("|/-\\-"),
+  );
+  bar.enable_steady_tick(250);
+  let (mut n_new, mut n_unchanged, mut n_changed, mut n_failed) = (0, 0, 0, 0);
+  let mut changes: BTreeMap<String, usize> = BTreeMap::new();
+  for entry in latest.values() {
+    bar.inc(1);
+
+    let website = match Website::from_url(&entry.url) {
+      Some(website) => website,
+      None => {
+        error!("Couldn't figure out the website of {}", entry.url);
+        n_failed += 1;
+        continue;
+      }
+    };
+
+    let body = match archive.read(entry) {
+      Ok(body) => body,
+      Err(e) => {
+        error!("Couldn't read archived copy of {}:{}", entry.url, e);
+        n_failed += 1;
+        continue;
+      }
+    };
+
+    let mut listing = match Listing::try_from_html(
+      &Html::parse_document(&body),
+      &entry.url,
+      &website,
+      entry.timestamp,
+    ) {
+      Ok(listing) => listing,
+      Err(e) => {
+        error!("Couldn't parse {} : {}", entry.url, e);
+        n_failed += 1;
+        continue;
+      }
+    };
+    listing.seen(entry.timestamp);
+    listing.set_first_seen(first_seen[&entry.url]);
+
+    // Listings are compared with what was parsed off the same page when it was fetched
+    match old.get_parsed(&entry.url, &entry.timestamp) {
+      Some(previous) => {
+        let fields = changed_fields(previous, &listing);
+        if fields.is_empty() {
+          n_unchanged += 1;
+        } else {
+          n_changed += 1;
+          for field in fields.into_iter() {
+            *changes.entry(field).or_insert(0) += 1;
+          }
+        }
+      }
+      None => n_new += 1,
+    }
+
+    // Listings that ended since their latest fetch keep on being over
+    let end = old
+      .get_latest(&entry.url)
+      .filter(|previous| !previous.status().is_live())
+      .map(|previous| (previous.status(), *previous.timestamp()));
+    new.add(listing.clone());
+    if let Some(validators) = old.get_validators(&entry.url) {
+      new.set_validators(validators.clone());
+    }
+    if let Some((status, timestamp)) = end {
+      listing.end(status, timestamp);
+      new.add(listing);
+    }
+  }
+  bar.finish();
+
+  println!(
+    "Reparsed {} listings into {} ({} unparsable)",
+    n_new + n_unchanged + n_changed,
+    dir.display(),
+    n_failed
+  );
+  println!(
+    "{} unchanged, {} changed, {} with no parse of their page in the current cache",
+    n_unchanged, n_changed, n_new
+  );
+  for (field, count) in changes.iter() {
+    println!("  {:<16}{:>8} changed", field, count);
+  }
+  println!(
+    "Make it the current cache with `truffles reparse --promote {}`",
+    name
+  );
+
+  Ok(())
+}
+
+/// Makes the generation `name` the current cache in `dir`, moving the current cache into a new
+/// generation, whose directory is returned, so that it can be promoted back
+fn promote(dir: &Path, name: &str) -> Result<PathBuf, Error> {
+  let generation = dir.join("generations").join(name);
+  if !generation.is_dir() {
+    return Err(Error::from(format!(
+      "No generation in {}",
+      generation.display()
+    )));
+  }
+  let previous = dir
+    .join("generations")
+    .join(Utc::now().format(GENERATION_FORMAT).to_string());
+  create_dir(&previous).map_err(Error::from)?;
+  for file in cache::files().iter() {
+    if dir.join(file).exists() {
+      rename(dir.join(file), previous.join(file)).map_err(Error::from)?;
+    }
+    if generation.join(file).exists() {
+      copy(generation.join(file), dir.join(file)).map_err(Error::from)?;
+    }
+  }
+  Ok(previous)
+}
+
+/// Lists the fields, other than the timestamps, whose values differ between two listings
+fn changed_fields(old: &Listing, new: &Listing) -> Vec<String> {
+  match (serde_json::to_value(old), serde_json::to_value(new)) {
+    (Ok(Value::Object(old)), Ok(Value::Object(new))) => new
+      .iter()
+      .filter(|(field, value)| {
+        !["timestamp", "first_seen", "last_seen"].contains(&field.as_str())
+          && old.get(*field) != Some(value)
+      })
+      .map(|(field, _)| field.clone())
+      .collect(),
+    _ => vec![String::from("kind")],
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::status::Status;
+  use chrono::TimeZone;
+  use std::env::temp_dir;
+  use std::fs::{create_dir_all, read_to_string, remove_dir_all, write};
+  use std::str::FromStr;
+
+  #[test]
+  fn reparse_previous_parse() {
+    let dir = temp_dir().join(format!("truffles-reparse-{}", std::process::id()));
+    let mut cache = Cache::load_from(dir.join("cache"));
+    let url = Url::from_str("https://www.bazaraki.com/adv/1_house/").unwrap();
+    let at = |hour| Utc.with_ymd_and_hms(2020, 6, 1, hour, 0, 0).unwrap();
+    let mut listing = Listing::default();
+    if let Listing::Property(prop) = &mut listing {
+      prop.url = url.clone();
+      prop.status = Status::Active;
+    }
+    listing.seen(at(10));
+    cache.add(listing.clone());
+    listing.end(Status::Removed, at(12));
+    cache.add(listing);
+
+    // The page fetched at 9 was parsed at 10, and the listing has been removed since
+    let parsed = cache.get_parsed(&url, &at(9)).unwrap();
+    assert_eq!(parsed.status(), Status::Active);
+    assert_eq!(*parsed.timestamp(), at(10));
+    assert_eq!(cache.get_latest(&url).unwrap().status(), Status::Removed);
+
+    remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn reparse_promote() {
+    let dir = temp_dir().join(format!("truffles-promote-{}", std::process::id()));
+    let generation = dir.join("generations").join("20200601T120000");
+    create_dir_all(&generation).unwrap();
+    write(dir.join("Property.csv"), "current").unwrap();
+    write(dir.join("validators.csv"), "current").unwrap();
+    write(generation.join("Property.csv"), "reparsed").unwrap();
+
+    // The current cache is kept as a generation of its own, and only replaced by what the
+    // promoted generation has
+    let previous = promote(&dir, "20200601T120000").unwrap();
+    assert_eq!(
+      read_to_string(dir.join("Property.csv")).unwrap(),
+      "reparsed"
+    );
+    assert!(!dir.join("validators.csv").exists());
+    assert_eq!(
+      read_to_string(previous.join("Property.csv")).unwrap(),
+      "current"
+    );
+    assert_eq!(
+      read_to_string(previous.join("validators.csv")).unwrap(),
+      "current"
+    );
+    assert!(promote(&dir, "20200101T000000").is_err());
+
+    remove_dir_all(dir).unwrap();
+  }
+}
@@ -0,0 +1,178 @@
+use csv::{Reader, Writer, WriterBuilder};
+use reqwest::{StatusCode, Url};
+use sha2::{Digest, Sha256};
+
+use std::collections::HashMap;
+use std::fs::{create_dir_all, read_to_string, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::error::Error;
+use crate::io::{url_deserializer, url_serializer};
+
+/// Index record of a single recorded response
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct Record {
+  /// URL
+  #[serde(
+    serialize_with = "url_serializer",
+    deserialize_with = "url_deserializer"
+  )]
+  url: Url,
+  /// HTTP status code
+  status: u16,
+  /// Body file, relative to the recording's directory
+  file: String,
+}
+
+/// A directory of recorded HTTP responses
+///
+/// `index.csv` maps every URL to a status code and a plain HTML file next to it, so hand-picked
+/// pages (e.g. the ones under `res/`) can be served just as well as recorded ones. Later records of
+/// the same URL take precedence.
+pub struct Recording {
+  dir: PathBuf,
+  records: Mutex<HashMap<Url, Record>>,
+  index: Option<Mutex<Writer<File>>>,
+}
+
+impl Recording {
+  /// Opens an existing recording to replay it
+  pub fn open(dir: &Path) -> Result<Self, Error> {
+    let records = read_records(&dir.join("index.csv"))?;
+    Ok(Self {
+      dir: dir.to_path_buf(),
+      records: Mutex::new(records),
+      index: None,
+    })
+  }
+
+  /// Opens a recording to add responses to, creating it if needed
+  pub fn create(dir: &Path) -> Result<Self, Error> {
+    create_dir_all(dir).map_err(Error::from)?;
+
+    let index_path = dir.join("index.csv");
+    let records = if index_path.exists() {
+      read_records(&index_path)?
+    } else {
+      HashMap::new()
+    };
+    let index = WriterBuilder::new()
+      .has_headers(
+        index_path
+          .metadata()
+          .map(|meta| meta.len() == 0)
+          .unwrap_or(true),
+      )
+      .from_writer(
+        OpenOptions::new()
+          .create(true)
+          .append(true)
+          .open(index_path)
+          .map_err(Error::from)?,
+      );
+
+    Ok(Self {
+      dir: dir.to_path_buf(),
+      records: Mutex::new(records),
+      index: Some(Mutex::new(index)),
+    })
+  }
+
+  /// Looks up the recorded response to `url`, if any
+  pub fn get(&self, url: &Url) -> Result<Option<(StatusCode, String)>, Error> {
+    let record = match self
+      .records
+      .lock()
+      .expect("INTERNAL ERROR: Recording lock poisoned")
+      .get(url)
+    {
+      Some(record) => record.clone(),
+      None => return Ok(None),
+    };
+    let status = StatusCode::from_u16(record.status).map_err(Error::from)?;
+    let body = read_to_string(self.dir.join(&record.file)).map_err(Error::from)?;
+    Ok(Some((status, body)))
+  }
+
+  /// Adds a response to the recording
+  pub fn put(&self, url: &Url, status: StatusCode, body: &str) -> Result<(), Error> {
+    let file = format!("{}.html", hex::encode(Sha256::digest(body.as_bytes())));
+    self.put_file(url, status, &file, body)
+  }
+
+  /// Adds a response to the recording, with its body in a file of the given name
+  pub fn put_file(
+    &self,
+    url: &Url,
+    status: StatusCode,
+    file: &str,
+    body: &str,
+  ) -> Result<(), Error> {
+    let index = self
+      .index
+      .as_ref()
+      .ok_or_else(|| Error::from("Recording was opened for replay only"))?;
+
+    let path = self.dir.join(file);
+    if !path.exists() {
+      File::create(&path)
+        .and_then(|mut f| f.write_all(body.as_bytes()))
+        .map_err(Error::from)?;
+    }
+
+    let record = Record {
+      url: url.clone(),
+      status: status.as_u16(),
+      file: file.to_string(),
+    };
+    let mut index = index
+      .lock()
+      .expect("INTERNAL ERROR: Recording index lock poisoned");
+    index.serialize(&record).map_err(Error::from)?;
+    index.flush().map_err(Error::from)?;
+    self
+      .records
+      .lock()
+      .expect("INTERNAL ERROR: Recording lock poisoned")
+      .insert(url.clone(), record);
+
+    Ok(())
+  }
+}
+
+fn read_records(path: &Path) -> Result<HashMap<Url, Record>, Error> {
+  Reader::from_path(path)
+    .map_err(|e| Error::from(format!("Couldn't open {}:{}", path.display(), e)))?
+    .into_deserialize::<Record>()
+    .map(|record| record.map(|record| (record.url.clone(), record)))
+    .collect::<Result<_, _>>()
+    .map_err(Error::from)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use std::str::FromStr;
+
+  #[test]
+  fn replay_fixtures() {
+    let recording = Recording::open(Path::new("res")).expect("Couldn't open res/ as a recording");
+
+    let url = Url::from_str("https://www.spitogatos.gr/en/property/1115470386").unwrap();
+    let (status, body) = recording
+      .get(&url)
+      .expect("Couldn't read recorded response")
+      .expect("No recorded response");
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+      body,
+      read_to_string("res/spitogatos_listing_1.html").unwrap()
+    );
+
+    let url = Url::from_str("https://www.spitogatos.gr/en/property/1").unwrap();
+    assert!(recording.get(&url).unwrap().is_none());
+    assert!(recording.put(&url, StatusCode::OK, "").is_err());
+  }
+}
@@ -0,0 +1,261 @@
+use reqwest::Url;
+
+use std::time::Duration;
+
+/// A single `Allow` or `Disallow` line
+#[derive(Debug)]
+struct Rule {
+  allow: bool,
+  path: String,
+}
+
+/// The lines that apply to a set of user agents
+#[derive(Debug, Default)]
+struct Group {
+  agents: Vec<String>,
+  rules: Vec<Rule>,
+  crawl_delay: Option<Duration>,
+}
+
+/// The rules of a site's `robots.txt`
+///
+/// Paths are matched as in RFC 9309: the longest matching rule wins, `Allow` wins ties, and rules may
+/// use `*` for any sequence of characters and a trailing `$` to anchor the end of the path.
+#[derive(Debug, Default)]
+pub struct Robots {
+  groups: Vec<Group>,
+  /// URLs given in `Sitemap` lines
+  sitemaps: Vec<String>,
+}
+
+impl Robots {
+  /// Rules for a site without a `robots.txt`
+  pub fn allow_all() -> Self {
+    Self::default()
+  }
+
+  /// Rules for a site whose `robots.txt` couldn't be fetched
+  pub fn disallow_all() -> Self {
+    Self::parse("User-agent: *\nDisallow: /")
+  }
+
+  pub fn parse(content: &str) -> Self {
+    let mut groups: Vec<Group> = vec![];
+    let mut sitemaps = vec![];
+    // Whether the group being read got past its `User-agent` lines
+    let mut in_rules = false;
+    for line in content.lines() {
+      let line = line.split('#').next().unwrap_or_default();
+      let (key, value) = match line.split_once(':') {
+        Some((key, value)) => (key.trim().to_ascii_lowercase(), value.trim()),
+        None => continue,
+      };
+      match key.as_str() {
+        "user-agent" => {
+          if in_rules || groups.is_empty() {
+            groups.push(Group::default());
+            in_rules = false;
+          }
+          if let Some(group) = groups.last_mut() {
+            // Some sites give a version along with the product token
+            let agent = value.split('/').next().unwrap_or_default();
+            group.agents.push(agent.trim().to_ascii_lowercase());
+          }
+        }
+        "allow" | "disallow" => {
+          in_rules = true;
+          // An empty `Disallow` allows everything, same as no rule at all
+          if let (Some(group), false) = (groups.last_mut(), value.is_empty()) {
+            group.rules.push(Rule {
+              allow: key == "allow",
+              path: value.to_string(),
+            });
+          }
+        }
+        "crawl-delay" => {
+          in_rules = true;
+          if let (Some(group), Ok(secs)) = (groups.last_mut(), value.parse::<f64>()) {
+            if secs.is_finite() && secs >= 0.0 {
+              group.crawl_delay = Some(Duration::from_secs_f64(secs));
+            }
+          }
+        }
+        // Sitemaps don't belong to any group
+        "sitemap" => sitemaps.push(value.to_string()),
+        _ => (),
+      }
+    }
+    Self { groups, sitemaps }
+  }
+
+  /// The groups that apply to `user_agent`: the ones naming the longest of its product tokens, or
+  /// else the ones for `*`
+  fn groups_for(&self, user_agent: &str) -> Vec<&Group> {
+    let tokens = product_tokens(user_agent);
+    let best = self
+      .groups
+      .iter()
+      .flat_map(|group| group.agents.iter())
+      .filter(|agent| tokens.contains(agent))
+      .max_by_key(|agent| agent.len())
+      .map(String::as_str)
+      .unwrap_or("*");
+    self
+      .groups
+      .iter()
+      .filter(|group| group.agents.iter().any(|agent| agent == best))
+      .collect()
+  }
+
+  /// Whether `user_agent` may fetch `url`
+  pub fn is_allowed(&self, user_agent: &str, url: &Url) -> bool {
+    let path = match url.query() {
+      Some(query) => format!("{}?{}", url.path(), query),
+      None => url.path().to_string(),
+    };
+    self
+      .groups_for(user_agent)
+      .into_iter()
+      .flat_map(|group| group.rules.iter())
+      .filter(|rule| matches(&rule.path, &path))
+      .max_by_key(|rule| (rule.path.len(), rule.allow))
+      .map(|rule| rule.allow)
+      .unwrap_or(true)
+  }
+
+  /// The sitemaps the site points to
+  pub fn sitemaps(&self) -> Vec<Url> {
+    self
+      .sitemaps
+      .iter()
+      .filter_map(|sitemap| Url::parse(sitemap).ok())
+      .collect()
+  }
+
+  /// How long `user_agent` is asked to wait between requests
+  pub fn crawl_delay(&self, user_agent: &str) -> Option<Duration> {
+    self
+      .groups_for(user_agent)
+      .into_iter()
+      .filter_map(|group| group.crawl_delay)
+      .max()
+  }
+}
+
+/// The product tokens of a `User-Agent` header, lowercased, e.g. `mozilla` and `chrome` for
+/// `Mozilla/5.0 (X11; Linux x86_64) Chrome/70.0.3538.77`, which are what `User-agent` lines name
+fn product_tokens(user_agent: &str) -> Vec<String> {
+  // Leave out comments, which are in parentheses
+  let mut products = String::new();
+  let mut depth = 0;
+  for c in user_agent.chars() {
+    match c {
+      '(' => depth += 1,
+      ')' if depth > 0 => depth -= 1,
+      _ if depth == 0 => products.push(c),
+      _ => (),
+    }
+  }
+  products
+    .split_whitespace()
+    .filter_map(|product| product.split('/').next())
+    .filter(|token| !token.is_empty())
+    .map(str::to_ascii_lowercase)
+    .collect()
+}
+
+/// Matches a path against a rule's pattern
+fn matches(pattern: &str, path: &str) -> bool {
+  let (pattern, anchored) = match pattern.strip_suffix('$') {
+    Some(pattern) => (pattern, true),
+    None => (pattern, false),
+  };
+  let parts: Vec<&str> = pattern.split('*').collect();
+  let (first, rest) = parts.split_first().expect("INTERNAL ERROR: Empty split");
+  if !path.starts_with(first) {
+    return false;
+  }
+  let mut pos = first.len();
+  for (i, part) in rest.iter().enumerate() {
+    if anchored && i == rest.len() - 1 {
+      return path.len() >= pos + part.len() && path.ends_with(part);
+    }
+    match path[pos..].find(part) {
+      Some(found) => pos += found + part.len(),
+      None => return false,
+    }
+  }
+  !anchored || pos == path.len()
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use std::str::FromStr;
+
+  #[test]
+  fn robots_rules() {
+    let robots = Robots::parse(
+      "# Rules\n\
+       User-agent: Googlebot\n\
+       Disallow: /\n\
+       \n\
+       User-agent: *\n\
+       User-agent: Chrome\n\
+       Disallow: /search\n\
+       Allow: /search/houses$\n\
+       Disallow: /*.pdf$\n\
+       Disallow: /adv/*/print\n\
+       Crawl-delay: 2.5\n\
+       \n\
+       Sitemap: https://www.example.com/sitemap.xml\n",
+    );
+    let url = |s| Url::from_str(&format!("https://www.example.com{}", s)).unwrap();
+    let agent = "Mozilla/5.0 (X11; Linux x86_64) Chrome/70.0.3538.77";
+
+    assert!(robots.is_allowed(agent, &url("/adv/123_house/")));
+    assert!(!robots.is_allowed(agent, &url("/search?q=house")));
+    assert!(robots.is_allowed(agent, &url("/search/houses")));
+    assert!(!robots.is_allowed(agent, &url("/search/houses/1")));
+    assert!(!robots.is_allowed(agent, &url("/files/plan.pdf")));
+    assert!(robots.is_allowed(agent, &url("/files/plan.pdf.html")));
+    assert!(!robots.is_allowed(agent, &url("/adv/123_house/print")));
+    assert!(!robots.is_allowed("Googlebot/2.1", &url("/adv/123_house/")));
+    assert_eq!(robots.crawl_delay(agent), Some(Duration::from_millis(2500)));
+    assert_eq!(robots.crawl_delay("Googlebot/2.1"), None);
+    assert_eq!(robots.sitemaps(), vec![url("/sitemap.xml")]);
+
+    assert!(Robots::allow_all().is_allowed(agent, &url("/search")));
+    assert!(!Robots::disallow_all().is_allowed(agent, &url("/")));
+  }
+
+  #[test]
+  fn robots_user_agents() {
+    let robots = Robots::parse(
+      "User-agent: a\n\
+       Disallow: /\n\
+       \n\
+       User-agent: bot\n\
+       Disallow: /\n\
+       \n\
+       User-agent: Chrome/70\n\
+       Disallow: /search\n",
+    );
+    let url = Url::from_str("https://www.example.com/adv/123_house/").unwrap();
+    let agent = "Mozilla/5.0 (X11; Linux x86_64) Chrome/70.0.3538.77";
+
+    // Names are matched against whole product tokens, not any part of the header
+    assert!(robots.is_allowed(agent, &url));
+    assert!(robots.is_allowed("Googlebot/2.1", &url));
+    assert!(!robots.is_allowed("bot", &url));
+    assert!(robots.is_allowed("Mozilla/5.0 (compatible; Bot/1.0)", &url));
+    assert!(!robots.is_allowed(
+      agent,
+      &Url::from_str("https://www.example.com/search").unwrap()
+    ));
+    assert_eq!(
+      product_tokens("Mozilla/5.0 (X11; Linux x86_64; rv:(nested)) Gecko/20100101 Firefox/85.0"),
+      ["mozilla", "gecko", "firefox"]
+    );
+  }
+}
@@ -0,0 +1,293 @@
+use chrono::{DateTime, Utc};
+
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, read_dir, read_to_string, rename, File};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::area::Area;
+use crate::error::Error;
+use crate::io::{data_path, timestamp_deserializer, timestamp_serializer};
+use crate::listing::Kind;
+use crate::site::Website;
+
+/// Metrics of a single crawl, kept as `~/.truffles/runs/<start time>.json`
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Run {
+  /// Time the crawl started
+  #[serde(
+    serialize_with = "timestamp_serializer",
+    deserialize_with = "timestamp_deserializer"
+  )]
+  pub started: DateTime<Utc>,
+  /// Time the crawl finished
+  #[serde(
+    serialize_with = "timestamp_serializer",
+    deserialize_with = "timestamp_deserializer"
+  )]
+  pub finished: DateTime<Utc>,
+  /// What the search covered
+  pub site: Website,
+  pub area: Option<Area>,
+  pub kind: Option<Kind>,
+  /// Seconds spent in every stage, in order
+  pub stages: Vec<(String, f64)>,
+  /// Number of pages requested
+  pub requests: usize,
+  /// Number of responses served from a recording, when replaying
+  #[serde(default)]
+  pub replayed: usize,
+  /// Bytes of page bodies downloaded, or replayed
+  pub bytes: u64,
+  /// Result pages walked
+  pub result_pages: usize,
+  /// Listings that weren't cached before
+  pub new: usize,
+  /// Cached listings that were fetched and parsed again
+  pub updated: usize,
+  pub unchanged: usize,
+  pub gone: usize,
+  pub expired: usize,
+  pub unparsable: usize,
+  pub failed: usize,
+  /// Images downloaded
+  #[serde(default)]
+  pub images: usize,
+  /// Number of unparsable listings by parser error
+  pub parse_errors: BTreeMap<String, usize>,
+  /// Why the crawl stopped early, if it reached one of its limits
+  #[serde(default)]
+  pub stopped: Option<String>,
+  /// Why the crawl failed, if it did
+  #[serde(default)]
+  pub error: Option<String>,
+}
+
+impl Run {
+  pub fn start(site: &Website, area: &Option<Area>, kind: &Option<Kind>) -> Self {
+    let now = Utc::now();
+    Self {
+      started: now,
+      finished: now,
+      site: site.clone(),
+      area: area.clone(),
+      kind: kind.clone(),
+      stages: vec![],
+      requests: 0,
+      replayed: 0,
+      bytes: 0,
+      result_pages: 0,
+      new: 0,
+      updated: 0,
+      unchanged: 0,
+      gone: 0,
+      expired: 0,
+      unparsable: 0,
+      failed: 0,
+      images: 0,
+      parse_errors: BTreeMap::new(),
+      stopped: None,
+      error: None,
+    }
+  }
+
+  pub fn stage(&mut self, name: &str, elapsed: Duration) {
+    self
+      .stages
+      .push((String::from(name), elapsed.as_secs_f64()));
+  }
+
+  pub fn unparsable(&mut self, error: &Error) {
+    self.unparsable += 1;
+    *self.parse_errors.entry(error.to_string()).or_insert(0) += 1;
+  }
+
+  /// Saves the run record
+  pub fn save(&self) -> Result<(), Error> {
+    self.save_in(&data_path("runs"))
+  }
+
+  fn save_in(&self, dir: &Path) -> Result<(), Error> {
+    create_dir_all(dir).map_err(Error::from)?;
+    let path = dir.join(format!("{}.json", self.started.format("%Y%m%dT%H%M%S")));
+    let tmp_path = path.with_extension("tmp");
+    serde_json::to_writer_pretty(File::create(&tmp_path).map_err(Error::from)?, self)
+      .map_err(Error::from)?;
+    rename(tmp_path, path).map_err(Error::from)
+  }
+
+  /// Reads back every run record, oldest first
+  pub fn all() -> Result<Vec<Run>, Error> {
+    Self::all_in(&data_path("runs"))
+  }
+
+  fn all_in(dir: &Path) -> Result<Vec<Run>, Error> {
+    if !dir.exists() {
+      return Ok(vec![]);
+    }
+    let mut paths = read_dir(dir)
+      .map_err(Error::from)?
+      .map(|entry| entry.map(|entry| entry.path()).map_err(Error::from))
+      .collect::<Result<Vec<_>, Error>>()?;
+    paths.retain(|path| path.extension().map(|ext| ext == "json").unwrap_or(false));
+    paths.sort();
+    paths
+      .iter()
+      .map(|path| {
+        let content = read_to_string(path).map_err(Error::from)?;
+        serde_json::from_str(&content)
+          .map_err(|e| Error::from(format!("Couldn't parse {}:{}", path.display(), e)))
+      })
+      .collect()
+  }
+
+  /// Prints what the run did
+  pub fn summarise(&self) {
+    println!(
+      "{} listings parsed ({} new), {} unchanged, {} gone, {} expired, {} unparsable, {} failed",
+      self.new + self.updated,
+      self.new,
+      self.unchanged,
+      self.gone,
+      self.expired,
+      self.unparsable,
+      self.failed
+    );
+    for (error, count) in self.parse_errors.iter() {
+      println!("  {:>6} x {}", count, error);
+    }
+    if self.replayed > 0 {
+      println!(
+        "{} responses replayed, {:.1} MB, {} result pages, {} images",
+        self.replayed,
+        self.bytes as f64 / 1_000_000.0,
+        self.result_pages,
+        self.images
+      );
+    } else {
+      println!(
+        "{} requests, {:.1} MB downloaded, {} result pages, {} images",
+        self.requests,
+        self.bytes as f64 / 1_000_000.0,
+        self.result_pages,
+        self.images
+      );
+    }
+    for (stage, seconds) in self.stages.iter() {
+      println!("  {:<20}{:>8.1}s", stage, seconds);
+    }
+  }
+}
+
+/// Lists past crawls, oldest first
+pub fn runs() -> Result<(), String> {
+  let runs = Run::all().map_err(|e| format!("Couldn't read run records:{}", e))?;
+  println!(
+    "{:<20}{:>8}  {:<32}{:>9}{:>9}{:>7}{:>8}{:>11}{:>7}",
+    "started", "minutes", "search", "requests", "MB", "new", "updated", "unparsable", "failed"
+  );
+  for run in runs.iter() {
+    let search = format!(
+      "{} {} {}",
+      run.site,
+      run
+        .area
+        .as_ref()
+        .map(|area| format!("{:?}", area))
+        .unwrap_or_else(|| String::from("*")),
+      run
+        .kind
+        .as_ref()
+        .map(|kind| format!("{:?}", kind))
+        .unwrap_or_else(|| String::from("*"))
+    );
+    println!(
+      "{:<20}{:>8.1}  {:<32}{:>9}{:>9.1}{:>7}{:>8}{:>11}{:>7}",
+      run.started.format("%F %T").to_string(),
+      (run.finished - run.started).num_seconds() as f64 / 60.0,
+      search,
+      run.requests,
+      run.bytes as f64 / 1_000_000.0,
+      run.new,
+      run.updated,
+      run.unparsable,
+      run.failed
+    );
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use chrono::Duration as TimeDelta;
+  use std::env::temp_dir;
+  use std::fs::{remove_dir_all, write};
+
+  #[test]
+  fn run_records() {
+    let dir = temp_dir().join(format!("truffles-runs-{}", std::process::id()));
+    let _ = remove_dir_all(&dir);
+    assert!(Run::all_in(&dir).unwrap().is_empty());
+
+    let mut first = Run::start(&Website::Bazaraki, &Some(Area::Limassol), &None);
+    first.started -= TimeDelta::days(1);
+    first.stage("listings", Duration::from_millis(1500));
+    first.unparsable(&Error::from("Couldn't find price"));
+    first.unparsable(&Error::from("Couldn't find price"));
+    first.error = Some(String::from("Couldn't save crawl progress"));
+    let second = Run::start(&Website::Spitogatos, &None, &Some(Kind::Property));
+    second.save_in(&dir).unwrap();
+    first.save_in(&dir).unwrap();
+
+    // Records come back oldest first
+    let runs = Run::all_in(&dir).unwrap();
+    assert_eq!(runs.len(), 2);
+    assert_eq!(runs[0].started, first.started);
+    assert_eq!(runs[0].area, Some(Area::Limassol));
+    assert_eq!(runs[0].stages, [(String::from("listings"), 1.5)]);
+    assert_eq!(runs[0].unparsable, 2);
+    assert_eq!(
+      runs[0].parse_errors.get("Error:Couldn't find price"),
+      Some(&2)
+    );
+    assert_eq!(
+      runs[0].error.as_deref(),
+      Some("Couldn't save crawl progress")
+    );
+    assert_eq!(runs[1].site, Website::Spitogatos);
+    assert_eq!(runs[1].error, None);
+
+    // Records saved before a field was added still read
+    let old = r#"{
+      "started": "2021-03-01 10:00:00 UTC",
+      "finished": "2021-03-01 10:30:00 UTC",
+      "site": "Bazaraki",
+      "area": null,
+      "kind": null,
+      "stages": [],
+      "requests": 120,
+      "bytes": 3000000,
+      "result_pages": 20,
+      "new": 5,
+      "updated": 1,
+      "unchanged": 0,
+      "gone": 0,
+      "expired": 2,
+      "unparsable": 0,
+      "failed": 0,
+      "parse_errors": {}
+    }"#;
+    write(dir.join("20210301T100000.json"), old).unwrap();
+    let runs = Run::all_in(&dir).unwrap();
+    assert_eq!(runs.len(), 3);
+    assert_eq!(runs[0].requests, 120);
+    assert_eq!((runs[0].replayed, runs[0].images), (0, 0));
+    assert_eq!(
+      (runs[0].stopped.clone(), runs[0].error.clone()),
+      (None, None)
+    );
+
+    remove_dir_all(&dir).unwrap();
+  }
+}
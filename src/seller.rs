@@ -0,0 +1,90 @@
+use regex::RegexBuilder;
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+use crate::cache::Cache;
+use crate::lookup::Lookup;
+use crate::site::Website;
+
+/// Who puts listings up for sale
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub enum Kind {
+  Agency,
+  Developer,
+  Private,
+}
+
+impl Lookup for Kind {
+  /// Tells developers apart from other businesses by their name
+  fn lookup(from: &str) -> Option<Self> {
+    if RegexBuilder::new(r"\b(developers?|development|construction|builders?|κατασκευ\w*)\b")
+      .case_insensitive(true)
+      .build()
+      .unwrap()
+      .find(from)
+      .is_some()
+    {
+      Some(Kind::Developer)
+    } else {
+      None
+    }
+  }
+}
+
+/// A seller, as kept in `sellers.csv` next to the cached listings
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Seller {
+  /// Unique Identifier, stable across the seller's listings
+  pub id: String,
+  /// Website
+  pub website: Website,
+  /// Name
+  pub name: String,
+  /// Agency, developer or private seller
+  pub kind: Kind,
+  /// Whether the website vouches for the seller
+  pub verified: bool,
+}
+
+/// Lists sellers by the number of live listings they have up
+pub fn sellers() -> Result<(), String> {
+  let cache = Cache::load();
+  let mut live: HashMap<&str, usize> = HashMap::new();
+  for listing in cache
+    .latest_listings()
+    .filter(|listing| listing.status().is_live())
+  {
+    if let Some(id) = listing.seller_id() {
+      *live.entry(id).or_insert(0) += 1;
+    }
+  }
+
+  let mut sellers: Vec<_> = cache.sellers().collect();
+  sellers.sort_by_key(|seller| Reverse(live.get(seller.id.as_str()).copied().unwrap_or(0)));
+  println!(
+    "{:<24}{:<40}{:<11}{:>9}{:>7}",
+    "id", "name", "kind", "verified", "live"
+  );
+  let mut by_kind: HashMap<Kind, usize> = HashMap::new();
+  for seller in sellers.iter() {
+    let count = live.get(seller.id.as_str()).copied().unwrap_or(0);
+    *by_kind.entry(seller.kind).or_insert(0) += count;
+    println!(
+      "{:<24}{:<40}{:<11}{:>9}{:>7}",
+      seller.id,
+      seller.name.chars().take(38).collect::<String>(),
+      format!("{:?}", seller.kind),
+      if seller.verified { "yes" } else { "no" },
+      count
+    );
+  }
+  println!(
+    "{} live listings by agencies, {} by developers, {} by private sellers",
+    by_kind.get(&Kind::Agency).unwrap_or(&0),
+    by_kind.get(&Kind::Developer).unwrap_or(&0),
+    by_kind.get(&Kind::Private).unwrap_or(&0)
+  );
+
+  Ok(())
+}
@@ -29,10 +29,23 @@ impl fmt::Display for Website {
 }
 
 impl Website {
+  /// Figures out which website a URL belongs to from its host
+  pub fn from_url(url: &Url) -> Option<Self> {
+    let host = url.host_str()?;
+    Self::all()
+      .into_iter()
+      .find(|website| website.get_root().host_str() == Some(host))
+  }
+
+  pub fn all() -> Vec<Website> {
+    vec![Self::Bazaraki, Self::Spitogatos, Self::ImmobilienScout24]
+  }
+
   pub fn get_root(&self) -> Url {
     match self {
       Self::Bazaraki => Url::parse("https://www.bazaraki.com").unwrap(),
-      _ => unimplemented!(),
+      Self::Spitogatos => Url::parse("https://www.spitogatos.gr").unwrap(),
+      Self::ImmobilienScout24 => Url::parse("https://www.immobilienscout24.de").unwrap(),
     }
   }
 }
@@ -0,0 +1,147 @@
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use flate2::read::GzDecoder;
+use regex::Regex;
+use reqwest::Url;
+
+use std::io::Read;
+
+use crate::error::Error;
+
+/// What a sitemap file lists
+#[derive(Debug, PartialEq)]
+pub enum Sitemap {
+  /// Other sitemaps, as found in a sitemap index
+  Index(Vec<Url>),
+  /// Pages, along with when they were last modified
+  UrlSet(Vec<(Url, Option<DateTime<Utc>>)>),
+}
+
+/// Turns the body of a sitemap file into text, gunzipping it if needed
+pub fn decode(body: &[u8]) -> Result<String, Error> {
+  if body.starts_with(&[0x1f, 0x8b]) {
+    let mut content = String::new();
+    GzDecoder::new(body)
+      .read_to_string(&mut content)
+      .map_err(|e| Error::from(format!("Couldn't gunzip sitemap:{}", e)))?;
+    Ok(content)
+  } else {
+    Ok(String::from_utf8_lossy(body).into_owned())
+  }
+}
+
+impl Sitemap {
+  /// Parses a sitemap or sitemap index
+  pub fn parse(content: &str) -> Result<Self, Error> {
+    let re_entry = Regex::new(r"(?s)<(sitemap|url)>(.*?)</(sitemap|url)>").unwrap();
+    let re_loc = Regex::new(r"(?s)<loc>\s*(.*?)\s*</loc>").unwrap();
+    let re_lastmod = Regex::new(r"(?s)<lastmod>\s*(.*?)\s*</lastmod>").unwrap();
+    let mut sitemaps = vec![];
+    let mut urls = vec![];
+    for entry in re_entry.captures_iter(content) {
+      let loc = match re_loc.captures(&entry[2]) {
+        Some(loc) => unescape(&loc[1]),
+        None => continue,
+      };
+      let url = match Url::parse(&loc) {
+        Ok(url) => url,
+        Err(e) => {
+          warn!("Couldn't parse {} as URL:{}", loc, e);
+          continue;
+        }
+      };
+      if &entry[1] == "sitemap" {
+        sitemaps.push(url);
+      } else {
+        let lastmod = re_lastmod
+          .captures(&entry[2])
+          .and_then(|lastmod| parse_lastmod(&lastmod[1]));
+        urls.push((url, lastmod));
+      }
+    }
+
+    match (sitemaps.is_empty(), urls.is_empty()) {
+      (false, true) => Ok(Self::Index(sitemaps)),
+      (true, _) if content.contains("<urlset") => Ok(Self::UrlSet(urls)),
+      (true, true) => Err(Error::from("Not a sitemap")),
+      _ => Err(Error::from("Sitemap lists both sitemaps and pages")),
+    }
+  }
+}
+
+/// Parses a W3C datetime, which may be a plain date
+fn parse_lastmod(s: &str) -> Option<DateTime<Utc>> {
+  DateTime::parse_from_rfc3339(s)
+    .map(|date| date.with_timezone(&Utc))
+    .ok()
+    .or_else(|| {
+      NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|date| Utc.from_utc_datetime(&date))
+    })
+}
+
+/// Undoes the entity escaping that sitemaps require in URLs
+fn unescape(s: &str) -> String {
+  s.replace("&amp;", "&")
+    .replace("&apos;", "'")
+    .replace("&quot;", "\"")
+    .replace("&gt;", ">")
+    .replace("&lt;", "<")
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use flate2::write::GzEncoder;
+  use flate2::Compression;
+  use std::io::Write;
+
+  #[test]
+  fn sitemap_parser() {
+    let index = r#"<?xml version="1.0" encoding="UTF-8"?>
+      <sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+        <sitemap><loc>https://www.example.com/sitemap-1.xml.gz</loc></sitemap>
+        <sitemap>
+          <loc>https://www.example.com/sitemap-2.xml.gz</loc>
+          <lastmod>2020-11-20</lastmod>
+        </sitemap>
+      </sitemapindex>"#;
+    assert_eq!(
+      Sitemap::parse(index).unwrap(),
+      Sitemap::Index(vec![
+        Url::parse("https://www.example.com/sitemap-1.xml.gz").unwrap(),
+        Url::parse("https://www.example.com/sitemap-2.xml.gz").unwrap(),
+      ])
+    );
+
+    let urlset = r#"<?xml version="1.0" encoding="UTF-8"?>
+      <urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+        <url>
+          <loc>https://www.example.com/adv/123_house/?a=1&amp;b=2</loc>
+          <lastmod>2020-11-20T10:30:00+02:00</lastmod>
+        </url>
+        <url><loc>https://www.example.com/about/</loc><lastmod>2020-11-20</lastmod></url>
+        <url><loc>https://www.example.com/contact/</loc></url>
+      </urlset>"#;
+    let mut encoder = GzEncoder::new(vec![], Compression::default());
+    encoder.write_all(urlset.as_bytes()).unwrap();
+    let gzipped = encoder.finish().unwrap();
+    match Sitemap::parse(&decode(&gzipped).unwrap()).unwrap() {
+      Sitemap::UrlSet(urls) => {
+        assert_eq!(urls.len(), 3);
+        assert_eq!(
+          urls[0].0.as_str(),
+          "https://www.example.com/adv/123_house/?a=1&b=2"
+        );
+        assert_eq!(urls[0].1.unwrap().to_rfc3339(), "2020-11-20T08:30:00+00:00");
+        assert_eq!(urls[1].1.unwrap().to_rfc3339(), "2020-11-20T00:00:00+00:00");
+        assert_eq!(urls[2].1, None);
+      }
+      sitemap => panic!("Expected a URL set, got {:?}", sitemap),
+    }
+
+    assert_eq!(decode(urlset.as_bytes()).unwrap(), urlset);
+    assert!(Sitemap::parse("<html></html>").is_err());
+  }
+}
@@ -0,0 +1,594 @@
+use chrono::{DateTime, Datelike, Duration, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use regex::Regex;
+use reqwest::Url;
+use scraper::{Html, Selector};
+
+use crate::area::Area;
+use crate::cond::Condition;
+use crate::error::Error;
+use crate::listing::{Kind as ListingKind, Listing};
+use crate::lookup::Lookup;
+use crate::parse::{
+  parse_coverage, parse_density, parse_description, parse_height, parse_images, parse_storeys, text,
+};
+use crate::plot::{Kind as PlotKind, Plot};
+use crate::precision::Precision;
+use crate::property::{Kind as PropertyKind, Property};
+use crate::seller::{Kind as SellerKind, Seller};
+use crate::site::Website;
+use crate::sites::SiteScraper;
+use crate::status::Status;
+
+use std::iter::Iterator;
+
+pub struct Bazaraki;
+
+impl SiteScraper for Bazaraki {
+  fn website(&self) -> Website {
+    Website::Bazaraki
+  }
+
+  fn fixture_prefix(&self) -> &'static str {
+    "listing"
+  }
+
+  fn result_pages(&self, search_url: &Url, html: &Html) -> Result<Vec<Url>, Error> {
+    let sel = Selector::parse("a.page-number.js-page-filter").expect("Couldn't parse selector");
+    let n_pages = html
+      .select(&sel)
+      .filter_map(|a| a.inner_html().parse::<u32>().ok())
+      .max()
+      .unwrap_or(1);
+
+    (1..=n_pages)
+      .map(|i| Url::parse(&format!("{}&page={}", search_url, i)).map_err(Error::from))
+      .collect()
+  }
+
+  fn listing_links(&self, html: &Html) -> Vec<Url> {
+    let root_url = self.website().get_root();
+    let sel = Selector::parse("a.announcement-block__title").unwrap();
+    html
+      .select(&sel)
+      .filter_map(|selection| match selection.value().attr("href") {
+        Some(url_str) => match root_url.join(url_str) {
+          Ok(url) => Some(url),
+          Err(e) => {
+            error!("Couldn't parse {} as URL:{}", url_str, e);
+            None
+          }
+        },
+        None => {
+          error!("No 'href' found in <a> element");
+          None
+        }
+      })
+      .collect()
+  }
+
+  fn is_listing_url(&self, url: &Url) -> bool {
+    url.path().starts_with("/adv/")
+  }
+
+  /// Bazaraki marks up listings as schema.org offers, whose availability tells if they're sold or
+  /// reserved
+  fn listing_status(&self, html: &Html) -> Status {
+    let sel = Selector::parse("[itemprop=\"offers\"] [itemprop=\"availability\"]")
+      .expect("INTERNAL ERROR: Couldn't parse selector");
+    html
+      .select(&sel)
+      .next()
+      .and_then(|availability| {
+        let availability = availability.value();
+        availability
+          .attr("href")
+          .or_else(|| availability.attr("content"))
+      })
+      .and_then(Status::from_availability)
+      .unwrap_or(Status::Active)
+  }
+
+  fn parse_listing(
+    &self,
+    html: &Html,
+    url: &Url,
+    fetched: DateTime<Utc>,
+  ) -> Result<Listing, Error> {
+    let mut listing = parse_bazaraki(html, url, fetched)?;
+    let (posted_at, views, title) = parse_details(html, fetched);
+    listing.set_details(posted_at, views, title);
+    listing.set_location(parse_location(html));
+    listing.set_seller(parse_seller(html));
+    listing.set_images(parse_images(html, url));
+    listing.set_description(parse_description(html, "div.announcement-description"));
+    Ok(listing)
+  }
+}
+
+fn parse_bazaraki(html: &Html, url: &Url, timestamp: DateTime<Utc>) -> Result<Listing, Error> {
+  // Common regular expressions
+  let re_int = Regex::new(r"[0-9]+").expect("INTERNAL ERROR: Couldn't build regex");
+
+  // Common selectors
+  let li_sel = Selector::parse("li").expect("INTERNAL ERROR: Couldn't parse selector");
+  let a_sel = Selector::parse("a").expect("INTERNAL ERROR: Couldn't parse selector");
+  let span_sel = Selector::parse("span").expect("INTERNAL ERROR: Couldn't parse selector");
+
+  // Parse UID
+  let id_sel: Selector =
+    Selector::parse("span[itemprop=\"sku\"").expect("INTERNAL ERROR: Couldn't parse selector");
+  let id: String = format!(
+    "bazaraki_{}",
+    html
+      .select(&id_sel)
+      .next()
+      .ok_or(Error::from("Couldn't select UID element"))?
+      .inner_html()
+  );
+
+  // Parse price
+  let price_sel: Selector =
+    Selector::parse("meta[itemprop=\"price\"]").expect("INTERNAL ERROR: Couldn't parse selector");
+  let price_str: &str = html
+    .select(&price_sel)
+    .next()
+    .ok_or(Error::from("Couldn't select price element"))?
+    .value()
+    .attr("content")
+    .ok_or(Error::from("Couldn't get price's 'content' attribute"))?;
+  let price: u32 = price_str.parse::<f32>().map_err(|e| Error::from(e))? as u32;
+
+  // Parse area
+  let area_sel: Selector =
+    Selector::parse("span[itemprop=\"address\"]").expect("INTERNAL ERROR: Couldn't parse selector");
+  let area_str = html
+    .select(&area_sel)
+    .next()
+    .ok_or(Error::from("Couldn't select area element"))?
+    .inner_html();
+  let area = match Area::lookup(&area_str) {
+    Some(area) => area,
+    None => return Err(Error::from("Couldn't parse area for")),
+  };
+
+  // Get useful html handles
+  let breadcrumbs_sel =
+    Selector::parse("ul.breadcrumbs").expect("INTERNAL ERROR: Couldn't parse selector");
+  let breadcrumbs_html = html
+    .select(&breadcrumbs_sel)
+    .next()
+    .ok_or(Error::from("Couldn't select breadcrumbs"))?
+    .html();
+  let chars_sel = Selector::parse("div.announcement-characteristics")
+    .expect("INTERNAL ERROR: Couldn't parse selector");
+  let chars = html
+    .select(&chars_sel)
+    .next()
+    .ok_or(Error::from("Couldn't select characteristics"))?;
+  let chars_html = chars.inner_html();
+  let desc_sel = Selector::parse("div.announcement-description")
+    .expect("INTERNAL ERROR: Couldn't parse selector");
+  let desc = html
+    .select(&desc_sel)
+    .next()
+    .ok_or(Error::from("Couldn't select description element"))?;
+  let desc_html = desc.inner_html();
+
+  // Parse size
+  let re_size = Regex::new(r"([0-9]+) m²").expect("INTERNAL ERROR: Couldn't parse regex");
+  let size = re_size
+    .captures(&chars_html)
+    .map(|g| g[1].parse::<f32>().map(|a| a as u32).ok())
+    .flatten();
+
+  // Figure out what kind of listing we're parsing
+  let kind = ListingKind::lookup(&breadcrumbs_html)
+    .ok_or(Error::from("Couldn't figure out listing kind"))?;
+
+  match kind {
+    ListingKind::Property => {
+      // Parse property kind
+      let kind = PropertyKind::lookup(&chars_html).ok_or(Error::from("Couldn't parse kind"))?;
+
+      // Parse condition
+      let cond = Condition::lookup(&chars_html);
+
+      // Parse bedrooms
+      let n_bedrooms = if Regex::new(r"[Ss]tudio")
+        .expect("INTERNAL ERROR: Couldn't parse regex")
+        .find(&chars_html)
+        .is_some()
+      {
+        Some(0)
+      } else {
+        let re_bedrooms =
+          Regex::new(r"[Bb]edrooms*").expect("INTERNAL ERROR: Couldn't parse regex");
+        match chars
+          .select(&li_sel)
+          .filter(|li| re_bedrooms.find(&li.inner_html()).is_some())
+          .next()
+        {
+          Some(li) => Some(
+            li.select(&a_sel)
+              .next()
+              .ok_or(Error::from("Couldn't select bedrooms"))?
+              .inner_html()
+              .trim()
+              .parse()
+              .map_err(|e| Error::from(format!("Couldn't parse bedrooms:{}", e)))?,
+          ),
+          None => None,
+        }
+      };
+
+      // Parse bathrooms
+      let re_bathrooms =
+        Regex::new(r"[Bb]athrooms*").expect("INTERNAL ERROR: Couldn't parse regex");
+      let n_bathrooms = chars
+        .select(&li_sel)
+        .filter(|li| re_bathrooms.find(&li.inner_html()).is_some())
+        .next()
+        .map(|li| {
+          li.select(&span_sel)
+            .filter(|span| re_int.find(&span.inner_html()).is_some())
+            .next()
+            .map(|span| {
+              let str_bathrooms = span.inner_html();
+              str_bathrooms.trim().parse().ok()
+            })
+        })
+        .flatten()
+        .flatten();
+
+      // Parse post code
+      let re_post =
+        Regex::new(r"[Pp]ostal\s+[Cc]ode").expect("INTERNAL ERROR: Couldn't parse regex");
+      let post_code = chars
+        .select(&li_sel)
+        .filter(|li| re_post.find(&li.inner_html()).is_some())
+        .next()
+        .map(|li| {
+          li.select(&span_sel)
+            .filter(|span| re_int.find(&span.inner_html()).is_some())
+            .next()
+            .map(|span| {
+              let str_post_code = span.inner_html();
+              str_post_code.trim().parse().ok()
+            })
+        })
+        .flatten()
+        .flatten();
+
+      // Parse year
+      let re_year = Regex::new(r"(20[0-3][0-9])|(19[0-9][0-9])").expect("Couldn't parse regex");
+      let year = re_year
+        .captures_iter(&desc_html)
+        .filter_map(|c| c[0].parse::<u32>().ok())
+        .min();
+
+      Ok(Listing::Property(Property::new(
+        id,
+        url.clone(),
+        Website::Bazaraki,
+        timestamp,
+        kind,
+        price,
+        area,
+        size,
+        cond,
+        year,
+        n_bedrooms,
+        n_bathrooms,
+        post_code,
+      )))
+    }
+    ListingKind::Plot => {
+      // Parse plot kind
+      let kind = PlotKind::lookup(&chars_html);
+
+      // Parse coverage
+      let coverage = parse_coverage(&desc_html)?;
+
+      // Parse density
+      let density = parse_density(&desc_html)?;
+
+      // Parse height
+      let height = parse_height(&desc_html)?;
+
+      // Parse storeys
+      let storeys = parse_storeys(&desc_html)?;
+
+      Ok(Listing::Plot(Plot::new(
+        id,
+        url.clone(),
+        Website::Bazaraki,
+        timestamp,
+        price,
+        area,
+        kind,
+        size,
+        coverage,
+        density,
+        height,
+        storeys,
+      )))
+    }
+  }
+}
+
+/// Parses when the listing was posted, how many times it was viewed and its title
+fn parse_details(
+  html: &Html,
+  fetched: DateTime<Utc>,
+) -> (Option<NaiveDateTime>, Option<u32>, Option<String>) {
+  let select_text = |sel: &str| {
+    let sel = Selector::parse(sel).expect("INTERNAL ERROR: Couldn't parse selector");
+    html.select(&sel).next().map(|element| text(&element))
+  };
+  let posted_at = select_text("span.date-meta").and_then(|posted| parse_posted(&posted, fetched));
+  let views = select_text("span.counter-views")
+    .and_then(|views| views.trim_start_matches("Views:").trim().parse().ok());
+  let title = select_text("h1.title-announcement").filter(|title| !title.is_empty());
+  (posted_at, views, title)
+}
+
+/// Parses who put the listing up, as told by the `offeredBy` block of the page
+///
+/// Businesses link to a shop page of theirs, while private sellers link to a list of their ads.
+fn parse_seller(html: &Html) -> Option<Seller> {
+  let author_sel =
+    Selector::parse("div.author-info").expect("INTERNAL ERROR: Couldn't parse selector");
+  let name_sel =
+    Selector::parse("[itemprop=\"name\"]").expect("INTERNAL ERROR: Couldn't parse selector");
+  let verified_sel =
+    Selector::parse("span.verified").expect("INTERNAL ERROR: Couldn't parse selector");
+  let ads_sel = Selector::parse("a.other-announcement-author")
+    .expect("INTERNAL ERROR: Couldn't parse selector");
+
+  let author = html.select(&author_sel).next()?;
+  let name_element = author.select(&name_sel).next()?;
+  let name = text(&name_element);
+  let user = name_element.value().attr("data-user")?;
+  let is_business = author
+    .select(&ads_sel)
+    .next()
+    .and_then(|a| a.value().attr("href"))
+    .map(|href| !href.contains("/items/author/"))
+    .unwrap_or(false);
+  let kind = if is_business {
+    SellerKind::lookup(&name).unwrap_or(SellerKind::Agency)
+  } else {
+    SellerKind::Private
+  };
+  let verified = author.value().classes().any(|class| class == "_verified")
+    || author.select(&verified_sel).next().is_some();
+
+  Some(Seller {
+    id: format!("bazaraki_{}", user),
+    website: Website::Bazaraki,
+    name,
+    kind,
+    verified,
+  })
+}
+
+/// Parses the coordinates of the listing off of its map, preferring the pin the seller put on it
+/// over the point the map is centered on
+fn parse_location(html: &Html) -> Option<(f64, f64, Precision)> {
+  let re_point = Regex::new(r"POINT\s*\(\s*(-?[0-9.]+)\s+(-?[0-9.]+)\s*\)")
+    .expect("INTERNAL ERROR: Couldn't build regex");
+  let pin_sel = Selector::parse("[data-coords]").expect("INTERNAL ERROR: Couldn't parse selector");
+  let map_sel =
+    Selector::parse("[data-default-lat]").expect("INTERNAL ERROR: Couldn't parse selector");
+
+  // Points are given as `SRID=4326;POINT (<lon> <lat>)`
+  let pin = html
+    .select(&pin_sel)
+    .filter_map(|element| re_point.captures(element.value().attr("data-coords")?))
+    .find_map(|point| {
+      Some((
+        point[2].parse().ok()?,
+        point[1].parse().ok()?,
+        Precision::Exact,
+      ))
+    });
+  pin.or_else(|| {
+    html.select(&map_sel).find_map(|element| {
+      let lat = element.value().attr("data-default-lat")?.parse().ok()?;
+      let lon = element.value().attr("data-default-lng")?.parse().ok()?;
+      Some((lat, lon, Precision::Approximate))
+    })
+  })
+}
+
+/// Parses a date such as `Posted: 04.04.2020 07:03`, where the day may also be `Today` or
+/// `Yesterday` as of when the page was fetched, in Cyprus
+fn parse_posted(posted: &str, fetched: DateTime<Utc>) -> Option<NaiveDateTime> {
+  let posted = posted.trim_start_matches("Posted:").trim();
+  let (day, time) = posted.rsplit_once(' ')?;
+  let time = NaiveTime::parse_from_str(time, "%H:%M").ok()?;
+  let today = fetched.with_timezone(&nicosia_offset(fetched)).date_naive();
+  let day = match day {
+    "Today" => today,
+    "Yesterday" => today.pred_opt()?,
+    day => NaiveDate::parse_from_str(day, "%d.%m.%Y").ok()?,
+  };
+  Some(day.and_time(time))
+}
+
+/// The offset of Cyprus time at `at`: EEST (UTC+3) from 01:00 UTC on the last Sunday of March to
+/// 01:00 UTC on the last Sunday of October, as across the EU, and EET (UTC+2) otherwise
+fn nicosia_offset(at: DateTime<Utc>) -> FixedOffset {
+  let switch = |month: u32| {
+    // The last Sunday of the month, counting back from its last day
+    let last = NaiveDate::from_ymd_opt(at.year(), month + 1, 1)?.pred_opt()?;
+    let sunday = last - Duration::days(last.weekday().num_days_from_sunday() as i64);
+    Some(sunday.and_hms_opt(1, 0, 0)?.and_utc())
+  };
+  let summer = match (switch(3), switch(10)) {
+    (Some(start), Some(end)) => at >= start && at < end,
+    _ => false,
+  };
+  let hours = if summer { 3 } else { 2 };
+  FixedOffset::east_opt(hours * 3600).expect("INTERNAL ERROR: Invalid offset")
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::sites::test::{listing_fixtures, read};
+  use chrono::TimeZone;
+  use scraper::Html;
+  use std::fs::read_to_string;
+  use std::str::FromStr;
+
+  #[test]
+  fn bazaraki_status() {
+    let page = read_to_string("res/listing_1.html").unwrap();
+    let start = page.find("<title>").unwrap() + "<title>".len();
+    let end = page.find("</title>").unwrap();
+    // Words such as "reserved" in the title don't tell anything about the listing itself
+    let reserved_parking = format!(
+      "{}Reserved parking, 2-bed flat, sold furnished{}",
+      &page[..start],
+      &page[end..]
+    );
+    assert_eq!(
+      Bazaraki.listing_status(&Html::parse_document(&reserved_parking)),
+      Status::Active
+    );
+
+    let sold = page.replace(
+      "<meta itemprop=\"price\" content=\"950000.00\">",
+      "<meta itemprop=\"price\" content=\"950000.00\">\
+       <link itemprop=\"availability\" href=\"https://schema.org/SoldOut\">",
+    );
+    assert_eq!(
+      Bazaraki.listing_status(&Html::parse_document(&sold)),
+      Status::Sold
+    );
+  }
+
+  #[test]
+  fn bazaraki_parser() {
+    let fixtures = listing_fixtures(&Bazaraki);
+    assert!(fixtures.len() >= 13);
+    for (url, path) in fixtures.iter() {
+      println!(
+        "{:?}\n",
+        parse_bazaraki(&read(path), url, Utc::now())
+          .unwrap_or_else(|e| panic!("Couldn't parse {}:{}", path, e))
+      );
+    }
+  }
+
+  #[test]
+  fn bazaraki_details() {
+    let fetched = Utc.with_ymd_and_hms(2020, 11, 20, 10, 30, 0).unwrap();
+    let at =
+      |y, m, d, h, min| NaiveDate::from_ymd_opt(y, m, d).and_then(|d| d.and_hms_opt(h, min, 0));
+
+    let (posted_at, views, title) = parse_details(&read("res/listing_1.html"), fetched);
+    assert_eq!(posted_at, at(2020, 4, 4, 7, 3));
+    assert_eq!(views, Some(58));
+    assert_eq!(title.as_deref(), Some("4 bedroom house / villa in ekali"));
+
+    let (posted_at, views, _) = parse_details(&read("res/listing_3.html"), fetched);
+    assert_eq!(posted_at, at(2020, 11, 19, 23, 35));
+    assert_eq!(views, Some(426));
+    let (posted_at, _, _) = parse_details(&read("res/listing_12.html"), fetched);
+    assert_eq!(posted_at, at(2020, 11, 20, 14, 5));
+  }
+
+  #[test]
+  fn bazaraki_posted() {
+    let utc = |y, m, d, h, min| Utc.with_ymd_and_hms(y, m, d, h, min, 0).unwrap();
+    let at =
+      |y, m, d, h, min| NaiveDate::from_ymd_opt(y, m, d).and_then(|d| d.and_hms_opt(h, min, 0));
+
+    // Just after midnight in Cyprus, it's still the day before in UTC
+    let fetched = utc(2020, 11, 19, 22, 30);
+    assert_eq!(
+      parse_posted("Today 00:10", fetched),
+      at(2020, 11, 20, 0, 10)
+    );
+    assert_eq!(
+      parse_posted("Yesterday 23:35", fetched),
+      at(2020, 11, 19, 23, 35)
+    );
+    let fetched = utc(2020, 7, 1, 21, 30);
+    assert_eq!(parse_posted("Today 00:10", fetched), at(2020, 7, 2, 0, 10));
+    let fetched = utc(2020, 7, 1, 20, 30);
+    assert_eq!(parse_posted("Today 23:10", fetched), at(2020, 7, 1, 23, 10));
+
+    // Summer time starts and ends at 01:00 UTC on the last Sunday of March and October
+    let hours = |at| nicosia_offset(at).local_minus_utc() / 3600;
+    assert_eq!(hours(utc(2020, 3, 29, 0, 59)), 2);
+    assert_eq!(hours(utc(2020, 3, 29, 1, 0)), 3);
+    assert_eq!(hours(utc(2021, 10, 31, 0, 59)), 3);
+    assert_eq!(hours(utc(2021, 10, 31, 1, 0)), 2);
+  }
+
+  #[test]
+  fn bazaraki_location() {
+    assert_eq!(
+      parse_location(&read("res/listing_1.html")),
+      Some((34.690023, 33.033141, Precision::Exact))
+    );
+    // The pin is all there is to go by when the map isn't centered anywhere
+    assert_eq!(
+      parse_location(&read("res/listing_3.html")),
+      Some((34.7107574, 33.029513, Precision::Exact))
+    );
+
+    // Without a pin, the map is only centered on the neighbourhood
+    let content = read_to_string("res/listing_1.html").unwrap().replace(
+      r#"data-coords="SRID=4326;POINT (33.033141 34.690023)""#,
+      r#"data-coords="""#,
+    );
+    assert_eq!(
+      parse_location(&Html::parse_document(&content)),
+      Some((34.70055095902465, 33.01553050228044, Precision::Approximate))
+    );
+    assert_eq!(parse_location(&Html::parse_fragment("<div></div>")), None);
+  }
+
+  #[test]
+  fn bazaraki_seller() {
+    let agency = parse_seller(&read("res/listing_1.html")).unwrap();
+    assert_eq!(agency.id, "bazaraki_98821");
+    assert_eq!(agency.name, "FIRST CLASS HOMES");
+    assert_eq!(agency.kind, SellerKind::Agency);
+    assert!(agency.verified);
+
+    let private = parse_seller(&read("res/listing_2.html")).unwrap();
+    assert_eq!(private.id, "bazaraki_453373");
+    assert_eq!(private.kind, SellerKind::Private);
+    assert!(!private.verified);
+  }
+
+  #[test]
+  fn bazaraki_results() {
+    let document = read("res/results_1.html");
+    let search_url = Url::from_str(
+      "https://www.bazaraki.com/real-estate/houses-and-villas-sale/lemesos-district-limassol/?ordering=newest",
+    )
+    .unwrap();
+
+    let pages = Bazaraki
+      .result_pages(&search_url, &document)
+      .expect("Couldn't get result pages");
+    assert_eq!(pages.len(), 212);
+    assert_eq!(pages[211].query(), Some("ordering=newest&page=212"));
+    assert_eq!(
+      Bazaraki.next_page(&document).unwrap().query(),
+      Some("ordering=newest&cities=12&page=2")
+    );
+
+    let links = Bazaraki.listing_links(&document);
+    assert!(!links.is_empty());
+    assert!(links
+      .iter()
+      .all(|url| url.host_str() == Some("www.bazaraki.com") && url.path().starts_with("/adv/")));
+  }
+}
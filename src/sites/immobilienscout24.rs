@@ -0,0 +1,433 @@
+use chrono::{DateTime, Utc};
+use regex::RegexBuilder;
+use reqwest::Url;
+use scraper::{Html, Selector};
+
+use std::collections::HashMap;
+
+use crate::area::Area;
+use crate::cond::Condition;
+use crate::error::Error;
+use crate::listing::Listing;
+use crate::lookup::Lookup;
+use crate::parse::{parse_description, parse_images, parse_integer, text};
+use crate::plot::{Kind as PlotKind, Plot};
+use crate::property::{Kind as PropertyKind, Property};
+use crate::site::Website;
+use crate::sites::SiteScraper;
+
+pub struct ImmobilienScout24;
+
+impl SiteScraper for ImmobilienScout24 {
+  fn website(&self) -> Website {
+    Website::ImmobilienScout24
+  }
+
+  fn fixture_prefix(&self) -> &'static str {
+    "immobilienscout24_listing"
+  }
+
+  fn result_pages(&self, search_url: &Url, html: &Html) -> Result<Vec<Url>, Error> {
+    let sel =
+      Selector::parse("ul.reactPagination a").expect("INTERNAL ERROR: Couldn't parse selector");
+    let n_pages = html
+      .select(&sel)
+      .filter_map(|a| text(&a).parse::<u32>().ok())
+      .max()
+      .unwrap_or(1);
+
+    Ok((1..=n_pages).map(|i| page_url(search_url, i)).collect())
+  }
+
+  fn next_page(&self, html: &Html) -> Option<Url> {
+    let sel =
+      Selector::parse("a[data-nav-next-page]").expect("INTERNAL ERROR: Couldn't parse selector");
+    let href = html.select(&sel).next()?.value().attr("href")?;
+    self.website().get_root().join(href).ok()
+  }
+
+  fn listing_links(&self, html: &Html) -> Vec<Url> {
+    let root_url = self.website().get_root();
+    let sel = Selector::parse("a.result-list-entry__brand-title-container")
+      .expect("INTERNAL ERROR: Couldn't parse selector");
+    html
+      .select(&sel)
+      .filter_map(|a| a.value().attr("href"))
+      .filter_map(|href| match root_url.join(href) {
+        Ok(mut url) => {
+          url.set_fragment(None);
+          Some(url)
+        }
+        Err(e) => {
+          error!("Couldn't parse {} as URL:{}", href, e);
+          None
+        }
+      })
+      .collect()
+  }
+
+  fn is_listing_url(&self, url: &Url) -> bool {
+    url.path().starts_with("/expose/")
+  }
+
+  fn parse_listing(
+    &self,
+    html: &Html,
+    url: &Url,
+    fetched: DateTime<Utc>,
+  ) -> Result<Listing, Error> {
+    let mut listing = parse_immobilienscout24(html, url, fetched)?;
+    listing.set_details(None, None, parse_title(html));
+    listing.set_images(parse_images(html, url));
+    listing.set_description(parse_description(html, "pre.is24qa-objektbeschreibung"));
+    Ok(listing)
+  }
+}
+
+/// Result pages are picked with the `pagenumber` query parameter
+fn page_url(search_url: &Url, page: u32) -> Url {
+  let mut url = search_url.clone();
+  if page > 1 {
+    url
+      .query_pairs_mut()
+      .append_pair("pagenumber", &page.to_string());
+  }
+  url
+}
+
+fn parse_immobilienscout24(
+  html: &Html,
+  url: &Url,
+  timestamp: DateTime<Utc>,
+) -> Result<Listing, Error> {
+  // Gather the criteria lists into a map
+  let dl_sel = Selector::parse("dl.grid").expect("INTERNAL ERROR: Couldn't parse selector");
+  let dt_sel = Selector::parse("dt").expect("INTERNAL ERROR: Couldn't parse selector");
+  let dd_sel = Selector::parse("dd").expect("INTERNAL ERROR: Couldn't parse selector");
+  let details: HashMap<String, String> = html
+    .select(&dl_sel)
+    .filter_map(|dl| {
+      let dt = dl.select(&dt_sel).next()?;
+      let dd = dl.select(&dd_sel).next()?;
+      Some((text(&dt).to_lowercase(), text(&dd)))
+    })
+    .collect();
+  if details.is_empty() {
+    return Err(Error::from("Couldn't select criteria"));
+  }
+
+  // Parse UID
+  let id_sel =
+    Selector::parse("div.is24-scoutid__content").expect("INTERNAL ERROR: Couldn't parse selector");
+  let id = format!(
+    "immobilienscout24_{}",
+    html
+      .select(&id_sel)
+      .next()
+      .and_then(|div| parse_integer(&text(&div)))
+      .ok_or_else(|| Error::from("Couldn't parse Scout-ID"))?
+  );
+
+  // Parse price
+  let price = details
+    .get("kaufpreis")
+    .and_then(|price| parse_integer(price))
+    .ok_or_else(|| Error::from("Couldn't parse price"))?;
+
+  // Parse area and post code off of the address, e.g. "10437 Berlin, Prenzlauer Berg"
+  let address_sel = Selector::parse("span.zip-region-and-country")
+    .expect("INTERNAL ERROR: Couldn't parse selector");
+  let address = html
+    .select(&address_sel)
+    .next()
+    .map(|span| text(&span))
+    .ok_or_else(|| Error::from("Couldn't select address element"))?;
+  let area = Area::lookup(&address)
+    .ok_or_else(|| Error::from(format!("Couldn't parse area from '{}'", address)))?;
+
+  // Parse description
+  let desc_sel = Selector::parse("pre.is24qa-objektbeschreibung")
+    .expect("INTERNAL ERROR: Couldn't parse selector");
+  let desc = html
+    .select(&desc_sel)
+    .next()
+    .map(|pre| text(&pre))
+    .unwrap_or_default();
+
+  // Figure out what kind of listing we're parsing
+  let breadcrumbs_sel =
+    Selector::parse("div.breadcrumb").expect("INTERNAL ERROR: Couldn't parse selector");
+  let breadcrumbs = html
+    .select(&breadcrumbs_sel)
+    .next()
+    .map(|div| text(&div))
+    .ok_or_else(|| Error::from("Couldn't select breadcrumbs"))?;
+
+  if breadcrumbs.contains("Grundstück") {
+    let size = details
+      .get("grundstücksfläche ca.")
+      .or_else(|| details.get("grundstück ca."))
+      .and_then(|size| parse_decimal(size))
+      .map(|size| size as u32);
+    let kind = details
+      .get("empfohlene nutzung")
+      .and_then(|usage| plot_kind(usage));
+    let coverage = details
+      .get("grz")
+      .and_then(|grz| parse_decimal(grz))
+      .map(|factor| (factor * 100.0).round() as u32);
+    let density = details
+      .get("gfz")
+      .and_then(|gfz| parse_decimal(gfz))
+      .map(|factor| (factor * 100.0).round() as u32);
+    let height = parse_height(&desc);
+    let storeys = parse_storeys(&desc);
+
+    Ok(Listing::Plot(Plot::new(
+      id,
+      url.clone(),
+      Website::ImmobilienScout24,
+      timestamp,
+      price,
+      area,
+      kind,
+      size,
+      coverage,
+      density,
+      height,
+      storeys,
+    )))
+  } else {
+    let typ = details
+      .get("typ")
+      .ok_or_else(|| Error::from("Couldn't find property type"))?;
+    let kind = property_kind(typ)
+      .ok_or_else(|| Error::from(format!("Couldn't parse kind from '{}'", typ)))?;
+    let size = details
+      .get("wohnfläche ca.")
+      .and_then(|size| parse_decimal(size))
+      .map(|size| size as u32);
+    let cond = details.get("objektzustand").map(|cond| condition(cond));
+    let year = details.get("baujahr").and_then(|year| parse_integer(year));
+    // "Zimmer" counts the living room in, so it's only a fallback for the number of bedrooms
+    let n_bedrooms = match details.get("schlafzimmer") {
+      Some(n) => n.trim().parse().ok(),
+      None => details
+        .get("zimmer")
+        .and_then(|n| parse_decimal(n))
+        .map(|n| (n.floor() as u8).saturating_sub(1)),
+    };
+    let n_bathrooms = details
+      .get("badezimmer")
+      .and_then(|n| n.trim().parse().ok());
+    let post_code = parse_integer(&address);
+
+    Ok(Listing::Property(Property::new(
+      id,
+      url.clone(),
+      Website::ImmobilienScout24,
+      timestamp,
+      kind,
+      price,
+      area,
+      size,
+      cond,
+      year,
+      n_bedrooms,
+      n_bathrooms,
+      post_code,
+    )))
+  }
+}
+
+/// Parses the title of the listing
+fn parse_title(html: &Html) -> Option<String> {
+  let sel = Selector::parse("h1#expose-title").expect("INTERNAL ERROR: Couldn't parse selector");
+  html
+    .select(&sel)
+    .next()
+    .map(|h1| text(&h1))
+    .filter(|title| !title.is_empty())
+}
+
+/// Parses a German-formatted number such as `1.020,5 m²`
+fn parse_decimal(from: &str) -> Option<f32> {
+  let number: String = from
+    .chars()
+    .skip_while(|c| !c.is_ascii_digit())
+    .take_while(|c| c.is_ascii_digit() || *c == ',' || *c == '.')
+    .filter(|c| *c != '.')
+    .map(|c| if c == ',' { '.' } else { c })
+    .collect();
+  number.parse().ok()
+}
+
+fn property_kind(typ: &str) -> Option<PropertyKind> {
+  let typ = typ.to_lowercase();
+  if typ.contains("maisonette") {
+    Some(PropertyKind::Maisonette)
+  } else if typ.contains("wohnung") || typ.contains("penthouse") || typ.contains("loft") {
+    Some(PropertyKind::Apartment)
+  } else if typ.contains("doppelhaus") {
+    Some(PropertyKind::Duplex)
+  } else if typ.contains("bungalow") {
+    Some(PropertyKind::Bungalow)
+  } else if typ.contains("villa") {
+    Some(PropertyKind::Villa)
+  } else if typ.contains("haus") {
+    Some(PropertyKind::House)
+  } else {
+    None
+  }
+}
+
+fn condition(cond: &str) -> Condition {
+  let cond = cond.to_lowercase();
+  if cond.contains("erstbezug") || cond.contains("neuwertig") {
+    Condition::New
+  } else if cond.contains("projektiert") || cond.contains("im bau") {
+    Condition::UnderConstruction
+  } else {
+    Condition::Resale
+  }
+}
+
+fn plot_kind(usage: &str) -> Option<PlotKind> {
+  let usage = usage.to_lowercase();
+  if usage.contains("wohn") {
+    Some(PlotKind::Residential)
+  } else if usage.contains("gewerbe") {
+    Some(PlotKind::Commercial)
+  } else if usage.contains("industrie") {
+    Some(PlotKind::Industrial)
+  } else if usage.contains("land") || usage.contains("forst") {
+    Some(PlotKind::Agricultural)
+  } else if usage.contains("freizeit") || usage.contains("touris") {
+    Some(PlotKind::Touristic)
+  } else {
+    None
+  }
+}
+
+/// Picks up heights such as "maximale Firsthöhe beträgt 9,5 m"
+fn parse_height(from: &str) -> Option<f32> {
+  RegexBuilder::new(r"h(ö|oe)he\D{0,20}?([0-9]+([.,][0-9]+)?)\s*m")
+    .case_insensitive(true)
+    .build()
+    .expect("INTERNAL ERROR: Couldn't parse regex")
+    .captures(from)
+    .and_then(|caps| caps[2].replace(",", ".").parse().ok())
+}
+
+/// Picks up storeys such as "mit zwei Vollgeschossen bebaubar" or "3 Geschosse"
+fn parse_storeys(from: &str) -> Option<u32> {
+  let caps = RegexBuilder::new(r"([0-9]+|ein|zwei|drei|vier|fünf)\s+(voll)?geschoss")
+    .case_insensitive(true)
+    .build()
+    .expect("INTERNAL ERROR: Couldn't parse regex")
+    .captures(from)?;
+  match caps[1].to_lowercase().as_str() {
+    "ein" => Some(1),
+    "zwei" => Some(2),
+    "drei" => Some(3),
+    "vier" => Some(4),
+    "fünf" => Some(5),
+    n => n.parse().ok(),
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::sites::test::{listing_fixtures, read};
+  use std::str::FromStr;
+
+  #[test]
+  fn immobilienscout24_parser() {
+    let mut listings = vec![];
+    for (url, path) in listing_fixtures(&ImmobilienScout24).iter() {
+      let listing = parse_immobilienscout24(&read(path), url, Utc::now())
+        .unwrap_or_else(|e| panic!("Couldn't parse {}:{}", path, e));
+      println!("{:?}\n", listing);
+      listings.push(listing);
+    }
+    assert!(listings.len() >= 3);
+
+    let url = Url::from_str("https://www.immobilienscout24.de/expose/118462951").unwrap();
+    let expected = Listing::Property(Property::new(
+      String::from("immobilienscout24_118462951"),
+      url,
+      Website::ImmobilienScout24,
+      *listings[0].timestamp(),
+      PropertyKind::Apartment,
+      349000,
+      Area::Berlin,
+      Some(78),
+      Some(Condition::Resale),
+      Some(1908),
+      Some(2),
+      Some(1),
+      Some(10437),
+    ));
+    assert_eq!(listings[0], expected);
+
+    let url = Url::from_str("https://www.immobilienscout24.de/expose/120117733").unwrap();
+    let expected = Listing::Property(Property::new(
+      String::from("immobilienscout24_120117733"),
+      url,
+      Website::ImmobilienScout24,
+      *listings[1].timestamp(),
+      PropertyKind::House,
+      1195000,
+      Area::Hamburg,
+      Some(164),
+      Some(Condition::Resale),
+      Some(1968),
+      Some(4),
+      Some(2),
+      Some(22359),
+    ));
+    assert_eq!(listings[1], expected);
+
+    let url = Url::from_str("https://www.immobilienscout24.de/expose/116558420").unwrap();
+    let expected = Listing::Plot(Plot::new(
+      String::from("immobilienscout24_116558420"),
+      url,
+      Website::ImmobilienScout24,
+      *listings[2].timestamp(),
+      1480000,
+      Area::Munich,
+      Some(PlotKind::Residential),
+      Some(612),
+      Some(35),
+      Some(70),
+      Some(9.5),
+      Some(2),
+    ));
+    assert_eq!(listings[2], expected);
+  }
+
+  #[test]
+  fn immobilienscout24_results() {
+    let html = read("res/immobilienscout24_results_1.html");
+    let search_url = Url::from_str(
+      "https://www.immobilienscout24.de/Suche/de/berlin/berlin/wohnung-kaufen?sorting=2",
+    )
+    .unwrap();
+
+    let pages = ImmobilienScout24
+      .result_pages(&search_url, &html)
+      .expect("Couldn't get result pages");
+    assert_eq!(pages.len(), 120);
+    assert_eq!(pages[0], search_url);
+    assert_eq!(pages[119].query(), Some("sorting=2&pagenumber=120"));
+    assert_eq!(ImmobilienScout24.next_page(&html), Some(pages[1].clone()));
+
+    let links = ImmobilienScout24.listing_links(&html);
+    assert_eq!(links.len(), 3);
+    assert_eq!(
+      links[0].as_str(),
+      "https://www.immobilienscout24.de/expose/118462951"
+    );
+  }
+}
@@ -0,0 +1,155 @@
+mod bazaraki;
+// Not registered until they're tested against pages recorded off the real sites, as their fixtures
+// were written by hand
+#[allow(dead_code)]
+mod immobilienscout24;
+#[allow(dead_code)]
+mod spitogatos;
+
+use chrono::{DateTime, Utc};
+use reqwest::Url;
+use scraper::{Html, Selector};
+
+use std::collections::HashMap;
+
+use crate::area::Area;
+use crate::error::Error;
+use crate::listing::{Kind, Listing};
+use crate::site::Website;
+use crate::status::Status;
+use crate::urls;
+
+/// Everything needed to crawl a single real estate website
+pub trait SiteScraper: Send + Sync {
+  /// The website this scraper handles
+  fn website(&self) -> Website;
+
+  /// What fixtures of listing pages are named, as in `res/<prefix>_<n>.html`
+  fn fixture_prefix(&self) -> &'static str;
+
+  /// Search result pages to start crawling from
+  fn search_roots(&self, area: Option<Area>, kind: Option<Kind>) -> Vec<Url> {
+    urls::get_search_roots(Some(self.website()), area, kind)
+  }
+
+  /// Discovers every result page of a search, given its first page
+  fn result_pages(&self, search_url: &Url, html: &Html) -> Result<Vec<Url>, Error>;
+
+  /// Follows the link to the next result page, if there is one
+  fn next_page(&self, html: &Html) -> Option<Url> {
+    let sel = Selector::parse("link[rel=next], a[rel=next]")
+      .expect("INTERNAL ERROR: Couldn't parse selector");
+    let href = html.select(&sel).next()?.value().attr("href")?;
+    self.website().get_root().join(href).ok()
+  }
+
+  /// Extracts the links to listings off of a result page
+  fn listing_links(&self, html: &Html) -> Vec<Url>;
+
+  /// Whether `url` points to a listing, as opposed to e.g. a category page
+  fn is_listing_url(&self, url: &Url) -> bool;
+
+  /// Parses a listing page fetched at `fetched`
+  fn parse_listing(&self, html: &Html, url: &Url, fetched: DateTime<Utc>)
+    -> Result<Listing, Error>;
+
+  /// Tells whether a listing page marks the listing as sold or reserved, which every site does in
+  /// its own way, if at all
+  fn listing_status(&self, _html: &Html) -> Status {
+    Status::Active
+  }
+}
+
+/// Keeps a scraper for every supported website
+pub struct Registry {
+  scrapers: HashMap<Website, Box<dyn SiteScraper>>,
+}
+
+impl Registry {
+  pub fn new() -> Self {
+    let mut registry = Self {
+      scrapers: HashMap::new(),
+    };
+    registry.register(Box::new(bazaraki::Bazaraki));
+    registry
+  }
+
+  fn register(&mut self, scraper: Box<dyn SiteScraper>) {
+    assert!(self.scrapers.insert(scraper.website(), scraper).is_none());
+  }
+
+  /// Looks up the scraper for the website `url` belongs to
+  pub fn get_for_url(&self, url: &Url) -> Result<&dyn SiteScraper, Error> {
+    let website = Website::from_url(url)
+      .ok_or_else(|| Error::from(format!("{} doesn't belong to a known website", url)))?;
+    self.get(&website)
+  }
+
+  pub fn get(&self, website: &Website) -> Result<&dyn SiteScraper, Error> {
+    self
+      .scrapers
+      .get(website)
+      .map(|scraper| scraper.as_ref())
+      .ok_or_else(|| Error::from(format!("{} isn't supported yet", website)))
+  }
+}
+
+impl Default for Registry {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use csv::Reader;
+  use std::fs::read_to_string;
+  use std::str::FromStr;
+
+  /// Reads and parses a fixture page
+  pub fn read(path: &str) -> Html {
+    let content = read_to_string(path).unwrap_or_else(|e| panic!("Couldn't read {}:{}", path, e));
+    Html::parse_document(&content)
+  }
+
+  /// The listing pages of a website among the fixtures, as indexed in `res/index.csv`, along with
+  /// the URL each one was fetched from, so that pages promoted out of the quarantine get tested too
+  pub fn listing_fixtures(scraper: &dyn SiteScraper) -> Vec<(Url, String)> {
+    Reader::from_path("res/index.csv")
+      .expect("Couldn't open res/index.csv")
+      .records()
+      .map(|record| record.expect("Couldn't read res/index.csv"))
+      .filter(|record| &record[1] == "200")
+      .map(|record| {
+        let url = Url::from_str(&record[0]).expect("Couldn't parse fixture URL");
+        (url, format!("res/{}", &record[2]))
+      })
+      .filter(|(url, _)| {
+        Website::from_url(url) == Some(scraper.website()) && scraper.is_listing_url(url)
+      })
+      .collect()
+  }
+
+  #[test]
+  fn listing_fixtures_by_site() {
+    let registry = Registry::new();
+    for website in Website::all() {
+      // Sites without a scraper yet have nothing to check
+      let scraper = match registry.get(&website) {
+        Ok(scraper) => scraper,
+        Err(_) => continue,
+      };
+      let fixtures = listing_fixtures(scraper);
+      assert!(!fixtures.is_empty(), "No fixtures of {}", website);
+      for (_, path) in fixtures.iter() {
+        assert!(
+          path.starts_with(&format!("res/{}_", scraper.fixture_prefix())),
+          "{} isn't named like a fixture of {}",
+          path,
+          website
+        );
+      }
+    }
+  }
+}
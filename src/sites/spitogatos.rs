@@ -0,0 +1,313 @@
+use chrono::{DateTime, Utc};
+use reqwest::Url;
+use scraper::{Html, Selector};
+
+use std::collections::HashMap;
+
+use crate::area::Area;
+use crate::cond::Condition;
+use crate::error::Error;
+use crate::listing::{Kind as ListingKind, Listing};
+use crate::lookup::Lookup;
+use crate::parse::{
+  parse_description, parse_height, parse_images, parse_integer, parse_storeys, text,
+};
+use crate::plot::{Kind as PlotKind, Plot};
+use crate::property::{Kind as PropertyKind, Property};
+use crate::site::Website;
+use crate::sites::SiteScraper;
+
+pub struct Spitogatos;
+
+impl SiteScraper for Spitogatos {
+  fn website(&self) -> Website {
+    Website::Spitogatos
+  }
+
+  fn fixture_prefix(&self) -> &'static str {
+    "spitogatos_listing"
+  }
+
+  fn result_pages(&self, search_url: &Url, html: &Html) -> Result<Vec<Url>, Error> {
+    let sel = Selector::parse("ul.pagination a").expect("INTERNAL ERROR: Couldn't parse selector");
+    let n_pages = html
+      .select(&sel)
+      .filter_map(|a| text(&a).parse::<u32>().ok())
+      .max()
+      .unwrap_or(1);
+
+    Ok((1..=n_pages).map(|i| page_url(search_url, i)).collect())
+  }
+
+  fn listing_links(&self, html: &Html) -> Vec<Url> {
+    let root_url = self.website().get_root();
+    let sel = Selector::parse("a.tile__link").expect("INTERNAL ERROR: Couldn't parse selector");
+    html
+      .select(&sel)
+      .filter_map(|a| a.value().attr("href"))
+      .filter_map(|href| match root_url.join(href) {
+        Ok(url) => Some(url),
+        Err(e) => {
+          error!("Couldn't parse {} as URL:{}", href, e);
+          None
+        }
+      })
+      .collect()
+  }
+
+  fn is_listing_url(&self, url: &Url) -> bool {
+    url.path().starts_with("/en/property/")
+  }
+
+  fn parse_listing(
+    &self,
+    html: &Html,
+    url: &Url,
+    fetched: DateTime<Utc>,
+  ) -> Result<Listing, Error> {
+    let mut listing = parse_spitogatos(html, url, fetched)?;
+    listing.set_details(None, None, parse_title(html));
+    listing.set_images(parse_images(html, url));
+    listing.set_description(parse_description(html, "section.property__description > p"));
+    Ok(listing)
+  }
+}
+
+/// Result pages other than the first one live under `<search path>/page_<n>`
+fn page_url(search_url: &Url, page: u32) -> Url {
+  let mut url = search_url.clone();
+  if page > 1 {
+    url.set_path(&format!(
+      "{}/page_{}",
+      search_url.path().trim_end_matches('/'),
+      page
+    ));
+  }
+  url
+}
+
+fn parse_spitogatos(html: &Html, url: &Url, timestamp: DateTime<Utc>) -> Result<Listing, Error> {
+  // Gather the characteristics list into a map
+  let dt_sel =
+    Selector::parse("dl.details-list > dt").expect("INTERNAL ERROR: Couldn't parse selector");
+  let dd_sel =
+    Selector::parse("dl.details-list > dd").expect("INTERNAL ERROR: Couldn't parse selector");
+  let details: HashMap<String, String> = html
+    .select(&dt_sel)
+    .zip(html.select(&dd_sel))
+    .map(|(dt, dd)| (text(&dt).to_lowercase(), text(&dd)))
+    .collect();
+  if details.is_empty() {
+    return Err(Error::from("Couldn't select characteristics"));
+  }
+
+  // Parse UID
+  let id = format!(
+    "spitogatos_{}",
+    details
+      .get("listing code")
+      .ok_or_else(|| Error::from("Couldn't find listing code"))?
+  );
+
+  // Parse price
+  let price = details
+    .get("price")
+    .and_then(|price| parse_integer(price))
+    .ok_or_else(|| Error::from("Couldn't parse price"))?;
+
+  // Parse area
+  let location_sel =
+    Selector::parse("h2.property__location").expect("INTERNAL ERROR: Couldn't parse selector");
+  let location = html
+    .select(&location_sel)
+    .next()
+    .map(|h2| text(&h2))
+    .ok_or_else(|| Error::from("Couldn't select location element"))?;
+  let area = Area::lookup(&location)
+    .ok_or_else(|| Error::from(format!("Couldn't parse area from '{}'", location)))?;
+
+  // Parse description
+  let desc_sel = Selector::parse("section.property__description")
+    .expect("INTERNAL ERROR: Couldn't parse selector");
+  let desc = html
+    .select(&desc_sel)
+    .next()
+    .map(|section| text(&section))
+    .unwrap_or_default();
+
+  // Figure out what kind of listing we're parsing
+  let category = details
+    .get("category")
+    .ok_or_else(|| Error::from("Couldn't find category"))?;
+
+  match ListingKind::lookup(category) {
+    Some(ListingKind::Plot) => {
+      let size = details
+        .get("plot area")
+        .or_else(|| details.get("area"))
+        .and_then(|size| parse_integer(size));
+      let kind = details
+        .get("zoning")
+        .and_then(|zoning| PlotKind::lookup(zoning));
+      let coverage = details
+        .get("coverage ratio")
+        .and_then(|coverage| parse_integer(coverage));
+      let density = details
+        .get("building coefficient")
+        .and_then(|density| density.replace(",", ".").trim().parse::<f32>().ok())
+        .map(|factor| (factor * 100.0).round() as u32);
+      let height = parse_height(&desc)?;
+      let storeys = parse_storeys(&desc)?;
+
+      Ok(Listing::Plot(Plot::new(
+        id,
+        url.clone(),
+        Website::Spitogatos,
+        timestamp,
+        price,
+        area,
+        kind,
+        size,
+        coverage,
+        density,
+        height,
+        storeys,
+      )))
+    }
+    _ => {
+      // Studios are single-room apartments
+      let is_studio = category.to_lowercase().contains("studio");
+      let kind = if is_studio {
+        PropertyKind::Apartment
+      } else {
+        PropertyKind::lookup(category)
+          .ok_or_else(|| Error::from(format!("Couldn't parse kind from '{}'", category)))?
+      };
+      let size = details.get("area").and_then(|size| parse_integer(size));
+      let cond = details
+        .get("condition")
+        .and_then(|cond| Condition::lookup(cond));
+      let year = details
+        .get("year of construction")
+        .and_then(|year| parse_integer(year));
+      let n_bedrooms = if is_studio {
+        Some(0)
+      } else {
+        details.get("bedrooms").and_then(|n| n.trim().parse().ok())
+      };
+      let n_bathrooms = details.get("bathrooms").and_then(|n| n.trim().parse().ok());
+      let post_code = details
+        .get("postcode")
+        .and_then(|post_code| parse_integer(post_code));
+
+      Ok(Listing::Property(Property::new(
+        id,
+        url.clone(),
+        Website::Spitogatos,
+        timestamp,
+        kind,
+        price,
+        area,
+        size,
+        cond,
+        year,
+        n_bedrooms,
+        n_bathrooms,
+        post_code,
+      )))
+    }
+  }
+}
+
+/// Parses the title of the listing
+fn parse_title(html: &Html) -> Option<String> {
+  let sel = Selector::parse("h1.property__title").expect("INTERNAL ERROR: Couldn't parse selector");
+  html
+    .select(&sel)
+    .next()
+    .map(|h1| text(&h1))
+    .filter(|title| !title.is_empty())
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::sites::test::{listing_fixtures, read};
+  use std::str::FromStr;
+
+  #[test]
+  fn spitogatos_parser() {
+    let mut listings = vec![];
+    for (url, path) in listing_fixtures(&Spitogatos).iter() {
+      let listing = parse_spitogatos(&read(path), url, Utc::now())
+        .unwrap_or_else(|e| panic!("Couldn't parse {}:{}", path, e));
+      println!("{:?}\n", listing);
+      listings.push(listing);
+    }
+    assert!(listings.len() >= 3);
+
+    let url = Url::from_str("https://www.spitogatos.gr/en/property/1115470386").unwrap();
+    let expected = Listing::Property(Property::new(
+      String::from("spitogatos_1115470386"),
+      url,
+      Website::Spitogatos,
+      *listings[0].timestamp(),
+      PropertyKind::Apartment,
+      150000,
+      Area::Attica,
+      Some(85),
+      None,
+      Some(1975),
+      Some(2),
+      Some(1),
+      Some(11362),
+    ));
+    assert_eq!(listings[0], expected);
+
+    let url = Url::from_str("https://www.spitogatos.gr/en/property/1112204871").unwrap();
+    let expected = Listing::Plot(Plot::new(
+      String::from("spitogatos_1112204871"),
+      url,
+      Website::Spitogatos,
+      *listings[1].timestamp(),
+      95000,
+      Area::Crete,
+      Some(PlotKind::Residential),
+      Some(1250),
+      Some(30),
+      Some(40),
+      Some(7.5),
+      Some(2),
+    ));
+    assert_eq!(listings[1], expected);
+
+    match &listings[2] {
+      Listing::Property(property) => assert_eq!(property.area, Area::Thessaloniki),
+      listing => panic!("Expected a property, got {:?}", listing),
+    }
+  }
+
+  #[test]
+  fn spitogatos_results() {
+    let html = read("res/spitogatos_results_1.html");
+    let search_url =
+      Url::from_str("https://www.spitogatos.gr/en/for_sale-homes/attica?sort=date_desc").unwrap();
+
+    let pages = Spitogatos
+      .result_pages(&search_url, &html)
+      .expect("Couldn't get result pages");
+    assert_eq!(pages.len(), 351);
+    assert_eq!(pages[0], search_url);
+    assert_eq!(
+      pages[350].as_str(),
+      "https://www.spitogatos.gr/en/for_sale-homes/attica/page_351?sort=date_desc"
+    );
+
+    let links = Spitogatos.listing_links(&html);
+    assert_eq!(links.len(), 4);
+    assert_eq!(
+      links[0].as_str(),
+      "https://www.spitogatos.gr/en/property/1115470386"
+    );
+  }
+}
@@ -0,0 +1,32 @@
+/// Where a listing is in its lifecycle
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub enum Status {
+  /// Up for sale
+  Active,
+  /// Taken down, as told by a 404/410 or a redirect away from the listing
+  Removed,
+  /// Still up, but marked as sold or reserved
+  Sold,
+  /// No longer showing up in search results
+  Expired,
+}
+
+impl Status {
+  /// Whether the listing can still be seen on its website
+  pub fn is_live(self) -> bool {
+    self == Self::Active || self == Self::Sold
+  }
+}
+
+impl Status {
+  /// Reads the schema.org availability of a listing's offer, e.g. `https://schema.org/SoldOut`,
+  /// which only tells whether it's sold or reserved
+  pub fn from_availability(availability: &str) -> Option<Self> {
+    match availability.trim().rsplit('/').next() {
+      Some("SoldOut") | Some("Reserved") | Some("OutOfStock") | Some("Discontinued") => {
+        Some(Self::Sold)
+      }
+      _ => None,
+    }
+  }
+}
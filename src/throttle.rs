@@ -1,28 +1,261 @@
-use std::thread;
+use chrono::{DateTime, Utc};
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::Url;
+use tokio::sync::Mutex;
+use tokio::time::delay_for;
+
+use std::collections::HashMap;
 use std::time::{Duration, Instant};
 
-pub struct Throttler {
-  last: Option<Instant>,
-  interval: Duration,
+/// Upper bound for how far a host's interval can be stretched after being told to back off, and for
+/// how long a `Retry-After` can hold off its requests
+const MAX_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Debug)]
+pub struct Budget {
+  /// Number of requests that can be fired back-to-back
+  pub burst: u32,
+  /// Time it takes for a single request to be replenished
+  pub interval: Duration,
 }
 
-impl Throttler {
-  pub fn new(interval: Option<Duration>) -> Self {
+impl Budget {
+  pub fn new(burst: Option<u32>, interval: Option<Duration>) -> Self {
     Self {
-      last: None,
+      burst: burst.unwrap_or(1).max(1),
       interval: interval.unwrap_or(Duration::from_secs(1)),
     }
   }
+}
+
+struct Bucket {
+  burst: f64,
+  /// The interval set by the budget or the site's Crawl-delay, which backing off only stretches
+  /// for a while
+  steady: Duration,
+  interval: Duration,
+  tokens: f64,
+  refilled: Instant,
+  blocked_until: Option<Instant>,
+}
+
+impl Bucket {
+  fn new(budget: &Budget) -> Self {
+    Self {
+      burst: budget.burst as f64,
+      steady: budget.interval,
+      interval: budget.interval,
+      tokens: budget.burst as f64,
+      refilled: Instant::now(),
+      blocked_until: None,
+    }
+  }
+
+  /// Takes a token if one is available, otherwise returns how long to wait before trying again
+  fn take(&mut self, now: Instant) -> Result<(), Duration> {
+    if let Some(until) = self.blocked_until {
+      if until > now {
+        return Err(until - now);
+      }
+      self.blocked_until = None;
+    }
+
+    if self.interval == Duration::from_secs(0) {
+      return Ok(());
+    }
+
+    let elapsed = now - self.refilled;
+    self.tokens =
+      (self.tokens + elapsed.as_secs_f64() / self.interval.as_secs_f64()).min(self.burst);
+    self.refilled = now;
+
+    if self.tokens >= 1.0 {
+      self.tokens -= 1.0;
+      Ok(())
+    } else {
+      Err(self.interval.mul_f64(1.0 - self.tokens))
+    }
+  }
+}
+
+/// An asynchronous rate limiter that keeps a separate token bucket for every host
+pub struct RateLimiter {
+  budget: Budget,
+  buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+  pub fn new(budget: Budget) -> Self {
+    Self {
+      budget,
+      buckets: Mutex::new(HashMap::new()),
+    }
+  }
 
-  pub fn tick(&mut self) {
-    match self.last {
-      Some(last) => {
-        while Instant::now() - last < self.interval {
-          thread::sleep(Duration::from_millis(100));
+  /// Waits until a request to `url`'s host is allowed to go out
+  pub async fn acquire(&self, url: &Url) {
+    let host = url.host_str().unwrap_or_default();
+    loop {
+      let wait = {
+        let mut buckets = self.buckets.lock().await;
+        let bucket = buckets
+          .entry(host.to_string())
+          .or_insert_with(|| Bucket::new(&self.budget));
+        match bucket.take(Instant::now()) {
+          Ok(()) => return,
+          Err(wait) => wait,
+        }
+      };
+      delay_for(wait).await;
+    }
+  }
+
+  /// Holds off all requests to `url`'s host for `delay`, up to `MAX_INTERVAL`, and halves its rate
+  /// until it's answering again
+  pub async fn back_off(&self, url: &Url, delay: Duration) {
+    let host = url.host_str().unwrap_or_default();
+    let delay = if delay > MAX_INTERVAL {
+      warn!(
+        "{} asked to hold off for {:?}, which is capped at {:?}",
+        host, delay, MAX_INTERVAL
+      );
+      MAX_INTERVAL
+    } else {
+      delay
+    };
+    let mut buckets = self.buckets.lock().await;
+    let bucket = buckets
+      .entry(host.to_string())
+      .or_insert_with(|| Bucket::new(&self.budget));
+    let until = Instant::now() + delay;
+    if bucket.blocked_until.map(|b| b < until).unwrap_or(true) {
+      bucket.blocked_until = Some(until);
+    }
+    bucket.interval = (bucket.interval * 2)
+      .max(Duration::from_millis(100))
+      .min(MAX_INTERVAL);
+    bucket.tokens = 0.0;
+    warn!(
+      "Backing off {} for {:?}, interval is now {:?}",
+      host, delay, bucket.interval
+    );
+  }
+
+  /// Brings the rate of `url`'s host back towards its steady rate after a successful request,
+  /// halving whatever backing off added to its interval
+  pub async fn recover(&self, url: &Url) {
+    let host = url.host_str().unwrap_or_default();
+    let mut buckets = self.buckets.lock().await;
+    if let Some(bucket) = buckets.get_mut(host) {
+      if bucket.interval > bucket.steady {
+        bucket.interval = bucket.steady + (bucket.interval - bucket.steady) / 2;
+        if bucket.interval - bucket.steady < Duration::from_millis(100) {
+          bucket.interval = bucket.steady;
+          info!(
+            "{} is back to one request every {:?}",
+            host, bucket.interval
+          );
         }
       }
-      None => {}
     }
-    self.last = Some(Instant::now());
+  }
+
+  /// Spaces requests to `url`'s host at least `interval` apart, one at a time
+  pub async fn slow_down(&self, url: &Url, interval: Duration) {
+    let host = url.host_str().unwrap_or_default();
+    let mut buckets = self.buckets.lock().await;
+    let bucket = buckets
+      .entry(host.to_string())
+      .or_insert_with(|| Bucket::new(&self.budget));
+    if bucket.steady < interval || bucket.burst > 1.0 {
+      bucket.steady = bucket.steady.max(interval).min(MAX_INTERVAL);
+      bucket.interval = bucket.interval.max(bucket.steady);
+      bucket.burst = 1.0;
+      bucket.tokens = bucket.tokens.min(1.0);
+      info!(
+        "Slowing down {} to one request every {:?}",
+        host, bucket.interval
+      );
+    }
+  }
+}
+
+/// Parses a `Retry-After` header, given either in seconds or as an HTTP date
+pub fn parse_retry_after(headers: &HeaderMap) -> Option<Duration> {
+  let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+  if let Ok(secs) = value.parse::<u64>() {
+    return Some(Duration::from_secs(secs));
+  }
+  let date = DateTime::parse_from_rfc2822(value).ok()?;
+  Some(
+    (date.with_timezone(&Utc) - Utc::now())
+      .to_std()
+      .unwrap_or_else(|_| Duration::from_secs(0)),
+  )
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use reqwest::header::HeaderValue;
+
+  #[test]
+  fn token_bucket() {
+    let mut bucket = Bucket::new(&Budget::new(Some(2), Some(Duration::from_secs(1))));
+    let now = bucket.refilled;
+    assert!(bucket.take(now).is_ok());
+    assert!(bucket.take(now).is_ok());
+    assert!(bucket.take(now).is_err());
+    assert!(bucket.take(now + Duration::from_millis(500)).is_err());
+    assert!(bucket.take(now + Duration::from_millis(1000)).is_ok());
+    assert!(bucket.take(now + Duration::from_secs(10)).is_ok());
+    assert!(bucket.take(now + Duration::from_secs(10)).is_ok());
+    assert!(bucket.take(now + Duration::from_secs(10)).is_err());
+  }
+
+  #[tokio::test]
+  async fn back_off_and_recover() {
+    let url = Url::parse("https://www.bazaraki.com/").unwrap();
+    let limiter = RateLimiter::new(Budget::new(Some(2), Some(Duration::from_secs(1))));
+    let interval = || async { limiter.buckets.lock().await["www.bazaraki.com"].interval };
+
+    // A Retry-After that's too long is capped
+    limiter.back_off(&url, Duration::from_secs(3600)).await;
+    let blocked_until = limiter.buckets.lock().await["www.bazaraki.com"].blocked_until;
+    assert!(blocked_until.unwrap() <= Instant::now() + MAX_INTERVAL);
+    limiter.back_off(&url, Duration::from_secs(0)).await;
+    assert_eq!(interval().await, Duration::from_secs(4));
+
+    // Successful requests bring the interval back to the budget's, and no further
+    limiter.recover(&url).await;
+    assert_eq!(interval().await, Duration::from_millis(2500));
+    for _ in 0..5 {
+      limiter.recover(&url).await;
+    }
+    assert_eq!(interval().await, Duration::from_secs(1));
+
+    // or to the Crawl-delay
+    limiter.slow_down(&url, Duration::from_secs(5)).await;
+    limiter.back_off(&url, Duration::from_secs(0)).await;
+    assert_eq!(interval().await, Duration::from_secs(10));
+    for _ in 0..10 {
+      limiter.recover(&url).await;
+    }
+    assert_eq!(interval().await, Duration::from_secs(5));
+  }
+
+  #[test]
+  fn retry_after_parser() {
+    let mut headers = HeaderMap::new();
+    assert_eq!(parse_retry_after(&headers), None);
+    headers.insert(RETRY_AFTER, HeaderValue::from_static("120"));
+    assert_eq!(parse_retry_after(&headers), Some(Duration::from_secs(120)));
+    headers.insert(
+      RETRY_AFTER,
+      HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+    );
+    assert_eq!(parse_retry_after(&headers), Some(Duration::from_secs(0)));
+    headers.insert(RETRY_AFTER, HeaderValue::from_static("soon"));
+    assert_eq!(parse_retry_after(&headers), None);
   }
 }
@@ -19,6 +19,21 @@ const URLS: &[(&str, Website, Option<Area>, Option<Kind>)] = &[
   ("https://www.bazaraki.com/real-estate/land-and-plot/lefkosia-district-nicosia/?ordering=newest", Website::Bazaraki, Some(Area::Lefkosia), Some(Kind::Plot)),
   ("https://www.bazaraki.com/real-estate/land-and-plot/lemesos-district-limassol/?ordering=newest", Website::Bazaraki, Some(Area::Limassol), Some(Kind::Plot)),
   ("https://www.bazaraki.com/real-estate/land-and-plot/pafos-district-paphos/?ordering=newest", Website::Bazaraki, Some(Area::Paphos), Some(Kind::Plot)),
+  ("https://www.spitogatos.gr/en/for_sale-homes/attica?sort=date_desc", Website::Spitogatos, Some(Area::Attica), Some(Kind::Property)),
+  ("https://www.spitogatos.gr/en/for_sale-homes/crete?sort=date_desc", Website::Spitogatos, Some(Area::Crete), Some(Kind::Property)),
+  ("https://www.spitogatos.gr/en/for_sale-homes/thessaloniki-prefecture?sort=date_desc", Website::Spitogatos, Some(Area::Thessaloniki), Some(Kind::Property)),
+  ("https://www.spitogatos.gr/en/for_sale-land_plots/attica?sort=date_desc", Website::Spitogatos, Some(Area::Attica), Some(Kind::Plot)),
+  ("https://www.spitogatos.gr/en/for_sale-land_plots/crete?sort=date_desc", Website::Spitogatos, Some(Area::Crete), Some(Kind::Plot)),
+  ("https://www.spitogatos.gr/en/for_sale-land_plots/thessaloniki-prefecture?sort=date_desc", Website::Spitogatos, Some(Area::Thessaloniki), Some(Kind::Plot)),
+  ("https://www.immobilienscout24.de/Suche/de/berlin/berlin/wohnung-kaufen?sorting=2", Website::ImmobilienScout24, Some(Area::Berlin), Some(Kind::Property)),
+  ("https://www.immobilienscout24.de/Suche/de/berlin/berlin/haus-kaufen?sorting=2", Website::ImmobilienScout24, Some(Area::Berlin), Some(Kind::Property)),
+  ("https://www.immobilienscout24.de/Suche/de/hamburg/hamburg/wohnung-kaufen?sorting=2", Website::ImmobilienScout24, Some(Area::Hamburg), Some(Kind::Property)),
+  ("https://www.immobilienscout24.de/Suche/de/hamburg/hamburg/haus-kaufen?sorting=2", Website::ImmobilienScout24, Some(Area::Hamburg), Some(Kind::Property)),
+  ("https://www.immobilienscout24.de/Suche/de/bayern/muenchen/wohnung-kaufen?sorting=2", Website::ImmobilienScout24, Some(Area::Munich), Some(Kind::Property)),
+  ("https://www.immobilienscout24.de/Suche/de/bayern/muenchen/haus-kaufen?sorting=2", Website::ImmobilienScout24, Some(Area::Munich), Some(Kind::Property)),
+  ("https://www.immobilienscout24.de/Suche/de/berlin/berlin/grundstueck-kaufen?sorting=2", Website::ImmobilienScout24, Some(Area::Berlin), Some(Kind::Plot)),
+  ("https://www.immobilienscout24.de/Suche/de/hamburg/hamburg/grundstueck-kaufen?sorting=2", Website::ImmobilienScout24, Some(Area::Hamburg), Some(Kind::Plot)),
+  ("https://www.immobilienscout24.de/Suche/de/bayern/muenchen/grundstueck-kaufen?sorting=2", Website::ImmobilienScout24, Some(Area::Munich), Some(Kind::Plot)),
 ];
 
 pub fn get_search_roots(
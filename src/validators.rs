@@ -0,0 +1,40 @@
+use reqwest::header::{HeaderMap, ETAG, LAST_MODIFIED};
+use reqwest::Url;
+use sha2::{Digest, Sha256};
+
+use crate::io::{url_deserializer, url_serializer};
+
+/// What's needed to tell whether a page changed since it was last fetched
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Validators {
+  /// URL
+  #[serde(
+    serialize_with = "url_serializer",
+    deserialize_with = "url_deserializer"
+  )]
+  pub url: Url,
+  /// `ETag` header
+  pub etag: Option<String>,
+  /// `Last-Modified` header
+  pub last_modified: Option<String>,
+  /// SHA-256 of the listing parsed off the body, without what changes on every fetch (see
+  /// `Listing::content_hash`), or of the body itself until it's parsed
+  pub hash: String,
+}
+
+impl Validators {
+  pub fn new(url: &Url, headers: &HeaderMap, body: &str) -> Self {
+    let header = |name| {
+      headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(String::from)
+    };
+    Self {
+      url: url.clone(),
+      etag: header(ETAG),
+      last_modified: header(LAST_MODIFIED),
+      hash: hex::encode(Sha256::digest(body.as_bytes())),
+    }
+  }
+}